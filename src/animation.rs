@@ -0,0 +1,452 @@
+//! Keyframe animation of the config.
+//!
+//! A [`Timeline`] holds snapshots of the config at points in time, and
+//! [`Timeline::sample`] interpolates between them. Continuous parameters are
+//! eased from one keyframe to the next, while discrete ones like the method
+//! keep the value of the earlier keyframe until the later one is reached.
+
+use std::{f64::consts::PI, fs, path::Path};
+
+use anyhow::Context;
+use glam::{DVec2, Vec3, Vec4};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{is_json, CameraConfig, Config};
+
+/// The keyframes of an animation, ordered by time except while one of them is
+/// being moved.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Timeline {
+    pub keyframes: Vec<Keyframe>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Keyframe {
+    /// Seconds from the start of the animation.
+    pub time: f64,
+    /// How the transition from this keyframe to the next one is timed.
+    #[serde(default)]
+    pub easing: Easing,
+    /// How the roots move to their positions in the next keyframe.
+    #[serde(default)]
+    pub root_path: RootPath,
+    #[serde(deserialize_with = "Config::deserialize_preset")]
+    pub config: Config,
+}
+
+impl Timeline {
+    /// Loads a timeline from a file, in the same formats as
+    /// [`Config::load`].
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("failed to read animation {}", path.display()))?;
+        let parse = || -> anyhow::Result<Self> {
+            Ok(if is_json(path) {
+                serde_json::from_str(&contents)?
+            } else {
+                toml::from_str(&contents)?
+            })
+        };
+        let mut timeline =
+            parse().with_context(|| format!("failed to parse animation {}", path.display()))?;
+        timeline.sort();
+        Ok(timeline)
+    }
+
+    /// Saves this timeline to a file, in the format selected by the file
+    /// extension (see [`Config::load`]).
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let contents = if is_json(path) {
+            serde_json::to_string_pretty(self)?
+        } else {
+            // Like `Config::save`, so that tables are emitted last.
+            toml::to_string(&toml::Value::try_from(self)?)?
+        };
+        fs::write(path, contents)
+            .with_context(|| format!("failed to write animation {}", path.display()))?;
+        Ok(())
+    }
+
+    pub fn apply(&mut self, event: &TimelineEvent, config: &Config) {
+        match *event {
+            TimelineEvent::AddKeyframe { time } => {
+                // A keyframe at the same time is replaced.
+                self.keyframes.retain(|keyframe| keyframe.time != time);
+                self.keyframes.push(Keyframe {
+                    time,
+                    easing: Default::default(),
+                    root_path: Default::default(),
+                    config: config.clone(),
+                });
+                self.sort();
+            }
+            TimelineEvent::RemoveKeyframe { index } => {
+                if index < self.keyframes.len() {
+                    self.keyframes.remove(index);
+                }
+            }
+            TimelineEvent::UpdateKeyframe { index } => {
+                if let Some(keyframe) = self.keyframes.get_mut(index) {
+                    keyframe.config = config.clone();
+                }
+            }
+            TimelineEvent::KeyframeTime { index, time } => {
+                if let Some(keyframe) = self.keyframes.get_mut(index) {
+                    keyframe.time = time.max(0.0);
+                }
+            }
+            TimelineEvent::SortKeyframes => {
+                self.sort();
+            }
+            TimelineEvent::KeyframeEasing { index, easing } => {
+                if let Some(keyframe) = self.keyframes.get_mut(index) {
+                    keyframe.easing = easing;
+                }
+            }
+            TimelineEvent::KeyframeRootPath { index, root_path } => {
+                if let Some(keyframe) = self.keyframes.get_mut(index) {
+                    keyframe.root_path = root_path;
+                }
+            }
+        }
+    }
+
+    fn sort(&mut self) {
+        self.keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+    }
+
+    /// The time of the last keyframe.
+    pub fn duration(&self) -> f64 {
+        self.keyframes.last().map_or(0.0, |keyframe| keyframe.time)
+    }
+
+    /// The config at `time`, or `None` if there are no keyframes.
+    ///
+    /// Before the first keyframe and after the last one, their configs are
+    /// returned unchanged.
+    pub fn sample(&self, time: f64) -> Option<Config> {
+        let next = self
+            .keyframes
+            .iter()
+            .position(|keyframe| keyframe.time > time);
+        let (from, to) = match next {
+            Some(0) => return Some(self.keyframes[0].config.clone()),
+            Some(next) => (&self.keyframes[next - 1], &self.keyframes[next]),
+            None => {
+                return self
+                    .keyframes
+                    .last()
+                    .map(|keyframe| keyframe.config.clone())
+            }
+        };
+        let t = (time - from.time) / (to.time - from.time);
+        Some(interpolate(
+            &from.config,
+            &to.config,
+            from.easing.apply(t),
+            from.root_path,
+        ))
+    }
+}
+
+/// Changes to a [`Timeline`] from the UI, see [`Timeline::apply`]. The
+/// current config is passed along, for the events that store it.
+#[derive(Debug, Clone)]
+pub enum TimelineEvent {
+    /// Adds the current config as a keyframe.
+    AddKeyframe {
+        time: f64,
+    },
+    RemoveKeyframe {
+        index: usize,
+    },
+    /// Replaces the config of a keyframe with the current one.
+    UpdateKeyframe {
+        index: usize,
+    },
+    /// Moves a keyframe in time, without sorting the keyframes again. This
+    /// keeps `index` referring to the same keyframe while its time is
+    /// dragged past another one.
+    KeyframeTime {
+        index: usize,
+        time: f64,
+    },
+    /// Sorts the keyframes by time, once [`TimelineEvent::KeyframeTime`] is
+    /// done.
+    SortKeyframes,
+    KeyframeEasing {
+        index: usize,
+        easing: Easing,
+    },
+    KeyframeRootPath {
+        index: usize,
+        root_path: RootPath,
+    },
+}
+
+/// How the time between two keyframes is mapped to the progress of the
+/// transition.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Easing {
+    #[default]
+    Linear,
+    /// Starts slowly and ends abruptly.
+    EaseIn,
+    /// Starts abruptly and ends slowly.
+    EaseOut,
+    /// Starts and ends slowly.
+    EaseInOut,
+}
+
+impl Easing {
+    pub const ALL: [Self; 4] = [Self::Linear, Self::EaseIn, Self::EaseOut, Self::EaseInOut];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Linear => "Linear",
+            Self::EaseIn => "Ease in",
+            Self::EaseOut => "Ease out",
+            Self::EaseInOut => "Ease in and out",
+        }
+    }
+
+    /// The progress after the fraction `t` of the time, both from 0 to 1.
+    pub fn apply(&self, t: f64) -> f64 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Self::Linear => t,
+            Self::EaseIn => t * t * t,
+            Self::EaseOut => 1.0 - (1.0 - t).powi(3),
+            Self::EaseInOut => t * t * (3.0 - 2.0 * t),
+        }
+    }
+}
+
+/// The path along which the roots move between two keyframes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RootPath {
+    /// Straight lines.
+    #[default]
+    Linear,
+    /// Arcs around the origin, interpolating the distance to the origin and
+    /// the angle, which turns the shorter way around.
+    Arc,
+}
+
+impl RootPath {
+    pub const ALL: [Self; 2] = [Self::Linear, Self::Arc];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Linear => "Linear",
+            Self::Arc => "Arc",
+        }
+    }
+
+    fn interpolate(&self, a: DVec2, b: DVec2, t: f64) -> DVec2 {
+        match self {
+            Self::Linear => a.lerp(b, t),
+            Self::Arc => {
+                let (angle_a, angle_b) = (a.y.atan2(a.x), b.y.atan2(b.x));
+                let turn = (angle_b - angle_a + PI).rem_euclid(2.0 * PI) - PI;
+                let angle = angle_a + turn * t;
+                let radius = a.length() + (b.length() - a.length()) * t;
+                radius * DVec2::new(angle.cos(), angle.sin())
+            }
+        }
+    }
+}
+
+/// The config at progress `t` from `a` to `b`, where 0 returns `a` exactly.
+///
+/// Roots are matched by index. If `b` has fewer roots, the extra roots of `a`
+/// stay in place until the next keyframe is reached.
+fn interpolate(a: &Config, b: &Config, t: f64, root_path: RootPath) -> Config {
+    let mut config = a.clone();
+    if t == 0.0 {
+        return config;
+    }
+    let lerp = |a: f64, b: f64| a + (b - a) * t;
+    config.num_iterations = lerp(a.num_iterations as f64, b.num_iterations as f64).round() as u32;
+    config.relaxation = a.relaxation.lerp(b.relaxation, t as f32);
+    config.nova_c = a.nova_c.lerp(b.nova_c, t as f32);
+    // Tolerances span orders of magnitude.
+    config.epsilon = geometric_lerp(a.epsilon as f64, b.epsilon as f64, t) as f32;
+    config.non_convergence_color = mix_colors(a.non_convergence_color, b.non_convergence_color, t);
+    config.cycle_color = mix_colors(a.cycle_color, b.cycle_color, t);
+    for (root, target) in config.roots.iter_mut().zip(&b.roots) {
+        root.position = root_path
+            .interpolate(root.position.as_dvec2(), target.position.as_dvec2(), t)
+            .as_vec2();
+        root.color = mix_colors(root.color, target.color, t);
+    }
+    config.update_coefficients();
+    config.camera = interpolate_camera(&a.camera, &b.camera, t);
+    config
+}
+
+/// Zooms exponentially, moving the camera so that the point that stays in
+/// the same place on screen during the whole transition does so.
+///
+/// That point is the fixed point of the similarity transform from the view of
+/// `a` to the view of `b`. Zooming in on a point that is visible in `a`
+/// keeps it steady instead of drifting off and back.
+fn interpolate_camera(a: &CameraConfig, b: &CameraConfig, t: f64) -> CameraConfig {
+    let zoom = geometric_lerp(a.zoom, b.zoom, t);
+    let offset = a.position.offset_to(&b.position);
+    // The fraction of the offset that is covered, seen from either end.
+    // Starting from the deeper end keeps its digits that `f64` can't hold.
+    let (width_a, width, width_b) = (1.0 / a.zoom, 1.0 / zoom, 1.0 / b.zoom);
+    let position = if (width_a - width_b).abs() <= 1e-12 * width_a {
+        a.position.translated(offset * t)
+    } else if width_b < width_a {
+        let remaining = (width - width_b) / (width_a - width_b);
+        b.position.translated(-offset * remaining)
+    } else {
+        let covered = (width_a - width) / (width_a - width_b);
+        a.position.translated(offset * covered)
+    };
+    CameraConfig { position, zoom }
+}
+
+fn geometric_lerp(a: f64, b: f64, t: f64) -> f64 {
+    if a > 0.0 && b > 0.0 {
+        a * (b / a).powf(t)
+    } else {
+        a + (b - a) * t
+    }
+}
+
+/// Mixes two colors in the Oklab color space, so that the brightness and
+/// saturation change evenly.
+fn mix_colors(a: Vec4, b: Vec4, t: f64) -> Vec4 {
+    let t = t as f32;
+    let lab = srgb_to_oklab(a.truncate()).lerp(srgb_to_oklab(b.truncate()), t);
+    oklab_to_srgb(lab).extend(a.w + (b.w - a.w) * t)
+}
+
+// The colors are written to the render target as they are, so they are in
+// the sRGB space.
+
+fn srgb_to_oklab(c: Vec3) -> Vec3 {
+    let linear = |x: f32| {
+        if x <= 0.04045 {
+            x / 12.92
+        } else {
+            ((x + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    let (r, g, b) = (linear(c.x), linear(c.y), linear(c.z));
+    let l = (0.412_221_47 * r + 0.536_332_55 * g + 0.051_445_995 * b).cbrt();
+    let m = (0.211_903_5 * r + 0.680_699_5 * g + 0.107_396_96 * b).cbrt();
+    let s = (0.088_302_46 * r + 0.281_718_85 * g + 0.629_978_7 * b).cbrt();
+    Vec3::new(
+        0.210_454_26 * l + 0.793_617_8 * m - 0.004_072_047 * s,
+        1.977_998_5 * l - 2.428_592_2 * m + 0.450_593_7 * s,
+        0.025_904_037 * l + 0.782_771_77 * m - 0.808_675_77 * s,
+    )
+}
+
+fn oklab_to_srgb(c: Vec3) -> Vec3 {
+    let l = (c.x + 0.396_337_78 * c.y + 0.215_803_76 * c.z).powi(3);
+    let m = (c.x - 0.105_561_346 * c.y - 0.063_854_17 * c.z).powi(3);
+    let s = (c.x - 0.089_484_18 * c.y - 1.291_485_5 * c.z).powi(3);
+    let r = 4.076_741_7 * l - 3.307_711_6 * m + 0.230_969_94 * s;
+    let g = -1.268_438 * l + 2.609_757_4 * m - 0.341_319_38 * s;
+    let b = -0.004_196_086_3 * l - 0.703_418_6 * m + 1.707_614_7 * s;
+    let encode = |x: f32| {
+        let x = x.clamp(0.0, 1.0);
+        if x <= 0.003_130_8 {
+            x * 12.92
+        } else {
+            1.055 * x.powf(1.0 / 2.4) - 0.055
+        }
+    };
+    Vec3::new(encode(r), encode(g), encode(b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keyframe(time: f64, num_iterations: u32) -> Keyframe {
+        Keyframe {
+            time,
+            easing: Easing::Linear,
+            root_path: Default::default(),
+            config: Config {
+                num_iterations,
+                ..Default::default()
+            },
+        }
+    }
+
+    fn camera(x: f64, y: f64, zoom: f64) -> CameraConfig {
+        CameraConfig {
+            position: DVec2::new(x, y).into(),
+            zoom,
+        }
+    }
+
+    #[test]
+    fn sample_hits_the_keyframes() {
+        assert!(Timeline::default().sample(0.0).is_none());
+
+        let timeline = Timeline {
+            keyframes: vec![keyframe(1.0, 10), keyframe(2.0, 20), keyframe(4.0, 40)],
+        };
+        let iterations = |time| timeline.sample(time).unwrap().num_iterations;
+        assert_eq!(iterations(0.0), 10);
+        assert_eq!(iterations(1.0), 10);
+        assert_eq!(iterations(1.5), 15);
+        assert_eq!(iterations(2.0), 20);
+        assert_eq!(iterations(3.0), 30);
+        assert_eq!(iterations(4.0), 40);
+        assert_eq!(iterations(5.0), 40);
+        assert_eq!(timeline.duration(), 4.0);
+    }
+
+    #[test]
+    fn keyframes_are_sorted_after_moving() {
+        let mut timeline = Timeline {
+            keyframes: vec![keyframe(1.0, 10), keyframe(2.0, 20), keyframe(4.0, 40)],
+        };
+        let config = Config::default();
+        // Dragging the first keyframe past the others.
+        for &time in &[1.5, 3.0, 5.0] {
+            timeline.apply(&TimelineEvent::KeyframeTime { index: 0, time }, &config);
+            assert_eq!(timeline.keyframes[0].time, time);
+            assert_eq!(timeline.keyframes[0].config.num_iterations, 10);
+        }
+        timeline.apply(&TimelineEvent::SortKeyframes, &config);
+        let iterations: Vec<_> = timeline
+            .keyframes
+            .iter()
+            .map(|keyframe| keyframe.config.num_iterations)
+            .collect();
+        assert_eq!(iterations, [20, 40, 10]);
+        assert_eq!(timeline.duration(), 5.0);
+    }
+
+    #[test]
+    fn interpolate_camera_ends_at_the_keyframes() {
+        let pairs = [
+            // Zooming in, zooming out, and panning at the same zoom.
+            (camera(0.0, 0.0, 1.0), camera(-0.75, 0.1, 1e6)),
+            (camera(-0.75, 0.1, 1e6), camera(0.0, 0.0, 1.0)),
+            (camera(0.0, 0.0, 2.0), camera(1.0, -1.0, 2.0)),
+        ];
+        for (a, b) in &pairs {
+            for &(t, end) in &[(0.0, a), (1.0, b)] {
+                let camera = interpolate_camera(a, b, t);
+                assert!((camera.zoom / end.zoom - 1.0).abs() < 1e-12);
+                // Within a tiny fraction of the view.
+                let offset = camera.position.offset_to(&end.position);
+                assert!(offset.length() * end.zoom < 1e-9, "{:?}", offset);
+            }
+        }
+    }
+}
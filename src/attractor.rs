@@ -0,0 +1,241 @@
+//! Attractors of the iteration, found by clustering the points where orbits
+//! end up, for [`Basins::Attractors`](crate::config::Basins::Attractors).
+//!
+//! This works without knowing the roots, and also finds the attracting cycles
+//! that some starting points fall into instead of converging. The renderers
+//! sample the orbits on a coarse grid in a first pass, pass the final
+//! positions to [`find`], and color each pixel by its attractor in a second
+//! pass.
+
+use std::collections::HashMap;
+
+use glam::{DVec2, Vec2, Vec4};
+use num_complex::Complex64;
+
+use crate::{
+    config::{hue_color, Config, Nova, Region},
+    cpu::Params,
+};
+
+/// The number of samples along each axis of the grid that the first pass
+/// renders.
+pub const GRID_SIZE: u32 = 128;
+
+/// The longest cycle that is detected if
+/// [`Config::max_cycle_period`](crate::config::Config::max_cycle_period)
+/// is 0, which only turns off the detection in each orbit.
+const MAX_PERIOD: usize = 16;
+
+/// Iterations from a cluster towards its cycle, before measuring the period.
+const SETTLE_ITERATIONS: usize = 200;
+
+/// The fraction of all samples that a cluster of orbits that did not converge
+/// needs to reach, to be checked for a cycle. Smaller clusters are mostly
+/// chaotic orbits that happened to end up close to each other.
+const MIN_CYCLE_FRACTION: f64 = 0.002;
+
+/// The final position of an orbit from the first pass.
+#[derive(Debug, Clone, Copy)]
+pub struct Sample {
+    pub position: Complex64,
+    /// Whether the step size dropped below the tolerance.
+    pub converged: bool,
+}
+
+/// A fixed point or attracting cycle of the iteration.
+#[derive(Debug, Clone)]
+pub struct Attractor {
+    /// The points of the cycle, which is a single point for fixed points.
+    pub points: Vec<Complex64>,
+    pub color: Vec4,
+    /// The index of the root at the fixed point, if there is one.
+    pub root: Option<usize>,
+    /// How many samples ended up at this attractor.
+    pub count: usize,
+}
+
+impl Attractor {
+    pub fn period(&self) -> usize {
+        self.points.len()
+    }
+}
+
+/// The region that the first pass samples, which is the square around the
+/// camera that is always visible.
+///
+/// It is the same for every tile of an image and every image size, so the
+/// attractors and their colors don't change between them.
+pub fn sample_region(config: &Config) -> Region {
+    config.camera.region(Vec2::ONE)
+}
+
+/// Final positions closer than this belong to the same attractor.
+pub fn radius(config: &Config) -> f64 {
+    // Converged orbits are within about `epsilon` of their fixed point, but
+    // orbits on a cycle only approach it geometrically.
+    (config.epsilon as f64).sqrt().max(1e-6)
+}
+
+/// Clusters the final positions of the orbits into attractors.
+///
+/// Clusters of converged orbits are fixed points. Clusters of orbits that did
+/// not converge are iterated further, and kept if they turn out to be on a
+/// cycle. With [`Nova::Mandelbrot`], each pixel iterates a different map, so
+/// no cycles are detected.
+///
+/// Fixed points at one of the roots in `config` get its color, all other
+/// attractors get new colors.
+pub fn find(config: &Config, samples: &[Sample]) -> Vec<Attractor> {
+    let radius = radius(config);
+    let clusters = cluster(samples, radius);
+
+    let mut attractors: Vec<Attractor> = clusters
+        .iter()
+        .filter(|cluster| cluster.converged)
+        .map(|cluster| Attractor {
+            points: vec![cluster.center],
+            color: Vec4::ZERO,
+            root: config.roots.iter().position(|root| {
+                (cluster.center - to_complex(root.position.as_dvec2())).norm() < radius
+            }),
+            count: cluster.count,
+        })
+        .collect();
+
+    let c = match config.nova {
+        Nova::Off => Some(Complex64::new(0.0, 0.0)),
+        Nova::Julia => Some(to_complex(config.nova_c.as_dvec2())),
+        Nova::Mandelbrot => None,
+    };
+    if let Some(c) = c {
+        let params = Params::new(config, &sample_region(config));
+        let min_count = (MIN_CYCLE_FRACTION * samples.len() as f64).max(2.0) as usize;
+        let max_period = match config.max_cycle_period {
+            0 => MAX_PERIOD,
+            max_period => max_period as usize,
+        };
+        let mut cycles: Vec<Attractor> = Vec::new();
+        for cluster in &clusters {
+            if cluster.converged || cluster.count < min_count {
+                continue;
+            }
+            // Clusters at other points of a known cycle are merged into it.
+            let known = cycles.iter_mut().find(|cycle| {
+                cycle
+                    .points
+                    .iter()
+                    .any(|&point| (point - cluster.center).norm() < radius)
+            });
+            if let Some(cycle) = known {
+                cycle.count += cluster.count;
+                continue;
+            }
+            if let Some(points) = cycle(&params, c, cluster.center, radius, max_period) {
+                cycles.push(Attractor {
+                    points,
+                    color: Vec4::ZERO,
+                    root: None,
+                    count: cluster.count,
+                });
+            }
+        }
+        attractors.extend(cycles);
+    }
+
+    // Sorting first keeps the colors of the largest attractors stable.
+    attractors.sort_by_key(|attractor| std::cmp::Reverse(attractor.count));
+    let mut new_colors = config.roots.len();
+    for attractor in &mut attractors {
+        attractor.color = match attractor.root {
+            Some(index) => config.roots[index].color,
+            None => {
+                new_colors += 1;
+                hue_color(new_colors - 1)
+            }
+        };
+    }
+    attractors
+}
+
+struct Cluster {
+    center: Complex64,
+    count: usize,
+    converged: bool,
+}
+
+/// Greedily groups the samples into clusters of the given radius, keeping
+/// converged and other orbits apart.
+///
+/// The clusters are looked up in a grid of cells the size of the radius, so
+/// this takes linear time even if the orbits end up all over the plane.
+fn cluster(samples: &[Sample], radius: f64) -> Vec<Cluster> {
+    let mut clusters: Vec<Cluster> = Vec::new();
+    let mut cells: HashMap<(i64, i64), Vec<usize>> = HashMap::new();
+    let cell = |z: Complex64| {
+        (
+            (z.re / radius).floor() as i64,
+            (z.im / radius).floor() as i64,
+        )
+    };
+    for sample in samples {
+        let z = sample.position;
+        if !z.is_finite() {
+            continue;
+        }
+        let (x, y) = cell(z);
+        let nearby = (x - 1..=x + 1)
+            .flat_map(|x| (y - 1..=y + 1).map(move |y| (x, y)))
+            .filter_map(|key| cells.get(&key))
+            .flatten()
+            .copied()
+            .find(|&i| {
+                clusters[i].converged == sample.converged
+                    && (clusters[i].center - z).norm() < radius
+            });
+        match nearby {
+            Some(i) => clusters[i].count += 1,
+            None => {
+                cells.entry((x, y)).or_default().push(clusters.len());
+                clusters.push(Cluster {
+                    center: z,
+                    count: 1,
+                    converged: sample.converged,
+                });
+            }
+        }
+    }
+    clusters
+}
+
+/// The points of the cycle that the orbit from `start` settles on, if it has a
+/// period of at least 2 and at most `max_period`.
+fn cycle(
+    params: &Params,
+    c: Complex64,
+    start: Complex64,
+    radius: f64,
+    max_period: usize,
+) -> Option<Vec<Complex64>> {
+    let step = |z: Complex64| z - (params.step(z) - c);
+    let mut z = start;
+    for _ in 0..SETTLE_ITERATIONS {
+        z = step(z);
+    }
+    let first = z;
+    let mut points = vec![first];
+    for _ in 0..max_period {
+        z = step(z);
+        if !z.is_finite() {
+            return None;
+        }
+        if (z - first).norm() < radius {
+            return if points.len() > 1 { Some(points) } else { None };
+        }
+        points.push(z);
+    }
+    None
+}
+
+fn to_complex(v: DVec2) -> Complex64 {
+    Complex64::new(v.x, v.y)
+}
@@ -0,0 +1,153 @@
+use std::collections::HashMap;
+
+use glam::Vec2;
+use winit::event::{ElementState, MouseButton, MouseScrollDelta, TouchPhase, WindowEvent};
+
+use crate::config::{CameraConfig, Config, ConfigChangeEvent};
+
+/// Zoom factor applied for each line scrolled with the mouse wheel.
+const ZOOM_PER_LINE: f64 = 1.2;
+
+/// How many pixels of a pixel-based scroll (e.g. on a touchpad) correspond to
+/// one line.
+const PIXELS_PER_LINE: f32 = 50.0;
+
+/// Moves the camera in response to mouse and touch input on the fractal view.
+///
+/// Dragging with the left mouse button or a single finger pans the camera,
+/// scrolling and pinching with two fingers zooms around the cursor.
+#[derive(Default)]
+pub struct CameraController {
+    cursor: Option<Vec2>,
+    dragging: bool,
+    touches: HashMap<u64, Vec2>,
+}
+
+impl CameraController {
+    /// Handles a window event, returning the resulting changes to the camera.
+    ///
+    /// `window_size` is the size of the fractal view in physical pixels. New
+    /// drags and scrolls are ignored while `ui_has_mouse` is set, so that they
+    /// don't interfere with the UI.
+    pub fn handle_event(
+        &mut self,
+        event: &WindowEvent,
+        config: &Config,
+        window_size: Vec2,
+        ui_has_mouse: bool,
+    ) -> Vec<ConfigChangeEvent> {
+        let mut camera = config.camera.clone();
+        match event {
+            WindowEvent::CursorMoved { position, .. } => {
+                let cursor = Vec2::new(position.x as f32, position.y as f32);
+                if let (true, Some(last_cursor)) = (self.dragging, self.cursor) {
+                    pan(&mut camera, cursor - last_cursor, window_size);
+                }
+                self.cursor = Some(cursor);
+            }
+            WindowEvent::CursorLeft { .. } => {
+                self.cursor = None;
+                self.dragging = false;
+            }
+            WindowEvent::MouseInput {
+                state,
+                button: MouseButton::Left,
+                ..
+            } => {
+                self.dragging = *state == ElementState::Pressed && !ui_has_mouse;
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                if let (false, Some(cursor)) = (ui_has_mouse, self.cursor) {
+                    let lines = match *delta {
+                        MouseScrollDelta::LineDelta(_, y) => y,
+                        MouseScrollDelta::PixelDelta(position) => {
+                            position.y as f32 / PIXELS_PER_LINE
+                        }
+                    };
+                    zoom(
+                        &mut camera,
+                        ZOOM_PER_LINE.powf(lines as f64),
+                        cursor,
+                        window_size,
+                    );
+                }
+            }
+            WindowEvent::Touch(touch) => {
+                let location = Vec2::new(touch.location.x as f32, touch.location.y as f32);
+                match touch.phase {
+                    TouchPhase::Started => {
+                        if !ui_has_mouse {
+                            self.touches.insert(touch.id, location);
+                        }
+                    }
+                    TouchPhase::Moved => {
+                        if self.touches.contains_key(&touch.id) {
+                            let (old_center, old_spread) = self.touch_centroid();
+                            self.touches.insert(touch.id, location);
+                            let (new_center, new_spread) = self.touch_centroid();
+                            pan(&mut camera, new_center - old_center, window_size);
+                            if old_spread > 0.0 && new_spread > 0.0 {
+                                zoom(
+                                    &mut camera,
+                                    (new_spread / old_spread) as f64,
+                                    new_center,
+                                    window_size,
+                                );
+                            }
+                        }
+                    }
+                    TouchPhase::Ended | TouchPhase::Cancelled => {
+                        self.touches.remove(&touch.id);
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        let mut changes = Vec::new();
+        if camera.position != config.camera.position {
+            changes.push(ConfigChangeEvent::CameraPosition(camera.position));
+        }
+        if camera.zoom != config.camera.zoom {
+            changes.push(ConfigChangeEvent::CameraZoom(camera.zoom));
+        }
+        changes
+    }
+
+    /// Returns the center of all active touches, and their average distance
+    /// from it.
+    fn touch_centroid(&self) -> (Vec2, f32) {
+        let count = self.touches.len() as f32;
+        let center = self
+            .touches
+            .values()
+            .fold(Vec2::ZERO, |sum, &touch| sum + touch)
+            / count;
+        let spread = self
+            .touches
+            .values()
+            .map(|touch| touch.distance(center))
+            .sum::<f32>()
+            / count;
+        (center, spread)
+    }
+}
+
+/// Moves the camera so that the content follows a pointer that moved by
+/// `delta` pixels.
+fn pan(camera: &mut CameraConfig, delta: Vec2, window_size: Vec2) {
+    let region = camera.region(window_size);
+    let origin = region.pixel_to_offset(Vec2::ZERO, window_size);
+    let offset = region.pixel_to_offset(delta, window_size) - origin;
+    camera.position = camera.position.translated(-offset);
+}
+
+/// Zooms the camera in by `factor`, keeping the point under the pixel `anchor`
+/// in place.
+fn zoom(camera: &mut CameraConfig, factor: f64, anchor: Vec2, window_size: Vec2) {
+    let anchor = camera
+        .region(window_size)
+        .pixel_to_offset(anchor, window_size);
+    camera.zoom *= factor;
+    camera.position = camera.position.translated(anchor - anchor / factor);
+}
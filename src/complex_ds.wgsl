@@ -0,0 +1,114 @@
+// Emulated double-precision complex arithmetic, prepended to `fractal.wgsl`
+// if the adapter does not support `SHADER_FLOAT64`.
+//
+// Real numbers are represented in "double-single" form: a `vec2<f32>` `a`
+// stands for the unevaluated sum `a.x + a.y`, where `a.y` holds the rounding
+// error of `a.x`. That gives about 48 bits of mantissa, at the cost of about
+// ten single-precision operations per double-single operation.
+//
+// A complex number `a` is a `vec4<f32>` holding two double-single numbers,
+// representing `a.xy + i * a.zw`.
+//
+// The error-free transformations below depend on every single-precision
+// operation being rounded exactly as written, and on `fma` being fused. A
+// driver that reassociates floating point operations loses the extra
+// precision, but the result is still as good as single precision.
+
+type Complex = vec4<f32>;
+
+// Exact sum of `a` and `b`, as the rounded sum and its rounding error.
+fn two_sum(a: f32, b: f32) -> vec2<f32> {
+    let s = a + b;
+    let v = s - a;
+    let e = (a - (s - v)) + (b - v);
+    return vec2<f32>(s, e);
+}
+
+// Like `two_sum`, but only correct if `abs(a) >= abs(b)`.
+fn quick_two_sum(a: f32, b: f32) -> vec2<f32> {
+    let s = a + b;
+    let e = b - (s - a);
+    return vec2<f32>(s, e);
+}
+
+// Exact product of `a` and `b`, as the rounded product and its rounding error.
+fn two_prod(a: f32, b: f32) -> vec2<f32> {
+    let p = a * b;
+    let e = fma(a, b, -p);
+    return vec2<f32>(p, e);
+}
+
+fn ds_add(a: vec2<f32>, b: vec2<f32>) -> vec2<f32> {
+    let s = two_sum(a.x, b.x);
+    let t = two_sum(a.y, b.y);
+    let u = quick_two_sum(s.x, s.y + t.x);
+    return quick_two_sum(u.x, u.y + t.y);
+}
+
+fn ds_sub(a: vec2<f32>, b: vec2<f32>) -> vec2<f32> {
+    return ds_add(a, -b);
+}
+
+fn ds_mul(a: vec2<f32>, b: vec2<f32>) -> vec2<f32> {
+    let p = two_prod(a.x, b.x);
+    return quick_two_sum(p.x, p.y + (a.x * b.y + a.y * b.x));
+}
+
+fn ds_mul_f32(a: vec2<f32>, b: f32) -> vec2<f32> {
+    let p = two_prod(a.x, b);
+    return quick_two_sum(p.x, p.y + a.y * b);
+}
+
+fn ds_div(a: vec2<f32>, b: vec2<f32>) -> vec2<f32> {
+    // Single-precision estimate, refined with one correction step.
+    let q1 = a.x / b.x;
+    let r = ds_sub(a, ds_mul_f32(b, q1));
+    let q2 = r.x / b.x;
+    return quick_two_sum(q1, q2);
+}
+
+fn c_from_f32(a: vec2<f32>) -> Complex {
+    return vec4<f32>(a.x, 0.0, a.y, 0.0);
+}
+
+// Converts a number that was split into a high and a low part on the CPU.
+fn c_from_split(hi: vec2<f32>, lo: vec2<f32>) -> Complex {
+    return vec4<f32>(two_sum(hi.x, lo.x), two_sum(hi.y, lo.y));
+}
+
+fn c_to_f32(a: Complex) -> vec2<f32> {
+    return a.xz;
+}
+
+fn c_add(a: Complex, b: Complex) -> Complex {
+    return vec4<f32>(ds_add(a.xy, b.xy), ds_add(a.zw, b.zw));
+}
+
+fn c_sub(a: Complex, b: Complex) -> Complex {
+    return c_add(a, -b);
+}
+
+fn c_mul(a: Complex, b: Complex) -> Complex {
+    return vec4<f32>(
+        ds_sub(ds_mul(a.xy, b.xy), ds_mul(a.zw, b.zw)),
+        ds_add(ds_mul(a.xy, b.zw), ds_mul(a.zw, b.xy)),
+    );
+}
+
+fn c_div(a: Complex, b: Complex) -> Complex {
+    let norm = ds_add(ds_mul(b.xy, b.xy), ds_mul(b.zw, b.zw));
+    return vec4<f32>(
+        ds_div(ds_add(ds_mul(a.xy, b.xy), ds_mul(a.zw, b.zw)), norm),
+        ds_div(ds_sub(ds_mul(a.zw, b.xy), ds_mul(a.xy, b.zw)), norm),
+    );
+}
+
+fn c_scale(a: Complex, s: f32) -> Complex {
+    return vec4<f32>(ds_mul_f32(a.xy, s), ds_mul_f32(a.zw, s));
+}
+
+// Only the step sizes and distances to the roots are measured, which don't
+// need more than single precision.
+fn c_length(a: Complex) -> f32 {
+    return length(c_to_f32(a));
+}
@@ -0,0 +1,49 @@
+// Double-precision complex arithmetic, prepended to `fractal.wgsl` if the
+// adapter supports `SHADER_FLOAT64`.
+//
+// A complex number `a` is a `vec2<f64>` representing `a.x + i * a.y`.
+
+type Complex = vec2<f64>;
+
+fn c_from_f32(a: vec2<f32>) -> Complex {
+    return vec2<f64>(a);
+}
+
+// Converts a number that was split into a high and a low part on the CPU.
+fn c_from_split(hi: vec2<f32>, lo: vec2<f32>) -> Complex {
+    return vec2<f64>(hi) + vec2<f64>(lo);
+}
+
+fn c_to_f32(a: Complex) -> vec2<f32> {
+    return vec2<f32>(a);
+}
+
+fn c_add(a: Complex, b: Complex) -> Complex {
+    return a + b;
+}
+
+fn c_sub(a: Complex, b: Complex) -> Complex {
+    return a - b;
+}
+
+fn c_mul(a: Complex, b: Complex) -> Complex {
+    return vec2<f64>(
+        a.x * b.x - a.y * b.y,
+        a.x * b.y + a.y * b.x,
+    );
+}
+
+fn c_div(a: Complex, b: Complex) -> Complex {
+    let inverse = vec2<f64>(b.x, -b.y) / (b.x * b.x + b.y * b.y);
+    return c_mul(a, inverse);
+}
+
+fn c_scale(a: Complex, s: f32) -> Complex {
+    return a * f64(s);
+}
+
+// Only the step sizes and distances to the roots are measured, which don't
+// need more than single precision.
+fn c_length(a: Complex) -> f32 {
+    return length(vec2<f32>(a));
+}
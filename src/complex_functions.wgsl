@@ -0,0 +1,76 @@
+// Elementary functions of complex numbers, used by the code that is generated
+// for `expr::Function`. This file is prepended to `fractal.wgsl`, after one of
+// the `complex_*.wgsl` files.
+//
+// WGSL has no transcendental functions for `f64`, so these are evaluated in
+// single precision whatever the precision of `Complex` is. Only `c_powi` keeps
+// the full precision.
+
+fn c_powi(a: Complex, n: i32) -> Complex {
+    var base = a;
+    if (n < 0) {
+        base = c_div(c_from_f32(vec2<f32>(1.0, 0.0)), a);
+    }
+    // Exponentiation by squaring.
+    var result = c_from_f32(vec2<f32>(1.0, 0.0));
+    var exponent: u32 = u32(abs(n));
+    loop {
+        if (exponent == 0u) {
+            break;
+        }
+        if ((exponent & 1u) == 1u) {
+            result = c_mul(result, base);
+        }
+        base = c_mul(base, base);
+        exponent = exponent >> 1u;
+    }
+    return result;
+}
+
+fn c_exp(a: Complex) -> Complex {
+    let v = c_to_f32(a);
+    return c_from_f32(exp(v.x) * vec2<f32>(cos(v.y), sin(v.y)));
+}
+
+// The principal branch, with the imaginary part in `(-pi, pi]`.
+fn c_log(a: Complex) -> Complex {
+    let v = c_to_f32(a);
+    return c_from_f32(vec2<f32>(log(length(v)), atan2(v.y, v.x)));
+}
+
+// The principal branch, with a non-negative real part.
+fn c_sqrt(a: Complex) -> Complex {
+    let v = c_to_f32(a);
+    let r = length(v);
+    let re = sqrt(0.5 * (r + v.x));
+    let im = sqrt(0.5 * (r - v.x));
+    return c_from_f32(vec2<f32>(re, select(im, -im, v.y < 0.0)));
+}
+
+fn c_sin(a: Complex) -> Complex {
+    let v = c_to_f32(a);
+    return c_from_f32(vec2<f32>(sin(v.x) * cosh(v.y), cos(v.x) * sinh(v.y)));
+}
+
+fn c_cos(a: Complex) -> Complex {
+    let v = c_to_f32(a);
+    return c_from_f32(vec2<f32>(cos(v.x) * cosh(v.y), -sin(v.x) * sinh(v.y)));
+}
+
+fn c_tan(a: Complex) -> Complex {
+    return c_div(c_sin(a), c_cos(a));
+}
+
+fn c_sinh(a: Complex) -> Complex {
+    let v = c_to_f32(a);
+    return c_from_f32(vec2<f32>(sinh(v.x) * cos(v.y), cosh(v.x) * sin(v.y)));
+}
+
+fn c_cosh(a: Complex) -> Complex {
+    let v = c_to_f32(a);
+    return c_from_f32(vec2<f32>(cosh(v.x) * cos(v.y), sinh(v.x) * sin(v.y)));
+}
+
+fn c_tanh(a: Complex) -> Complex {
+    return c_div(c_sinh(a), c_cosh(a));
+}
@@ -1,64 +1,614 @@
-use glam::{Vec2, Vec4};
+use std::{fs, path::Path};
 
+use anyhow::{ensure, Context};
+use glam::{DVec2, Vec2, Vec4};
+use num_complex::Complex64;
+use serde::{
+    de::{self, IgnoredAny},
+    Deserialize, Deserializer, Serialize,
+};
+
+use crate::{expr::Function, poly, precise::PrecisePoint};
+
+/// The highest supported order of [`Method::Householder`].
+pub const MAX_HOUSEHOLDER_ORDER: u32 = 16;
+
+/// The highest multiplicity of a root, which limits the polynomial of a
+/// single root to the degree that [`poly::parse`] accepts.
+pub const MAX_MULTIPLICITY: u32 = poly::MAX_DEGREE as u32;
+
+/// A region is resolved by a floating point type as long as a pixel spans at
+/// least this many representable values around its center. Below that, the
+/// rounding errors become visible as blocks.
+const MIN_PIXEL_ULPS: f64 = 16.0;
+
+/// How far along the real axis [`ConfigChangeEvent::DuplicateRoot`] moves the
+/// copy of a root.
+pub const DUPLICATE_OFFSET: f32 = 0.1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Config {
     pub num_iterations: u32,
+    pub method: Method,
+    /// Order of [`Method::Householder`], from 1 to [`MAX_HOUSEHOLDER_ORDER`].
+    pub householder_order: u32,
+    /// Complex factor `a` that each Newton step is multiplied with, i.e. each
+    /// iteration moves `z` to `z - a * p(z) / p'(z)`. The plain Newton method
+    /// has `a = 1`.
+    pub relaxation: Vec2,
+    /// Whether to add a constant `c` to each step, see [`Nova`].
+    pub nova: Nova,
+    /// The constant `c` of [`Nova::Julia`].
+    pub nova_c: Vec2,
+    /// Step size below which an iteration is considered to have converged.
+    pub epsilon: f32,
+    pub coloring: Coloring,
+    pub basins: Basins,
+    /// Color of the points that have not converged to a root after
+    /// `num_iterations`.
+    pub non_convergence_color: Vec4,
+    /// The longest period of the attracting cycles that are detected in each
+    /// orbit, or 0 to not look for cycles.
+    pub max_cycle_period: u32,
+    /// Color of the points whose orbit fell into a cycle, unless the cycle
+    /// has a color of its own from [`Basins::Attractors`].
+    pub cycle_color: Vec4,
+    /// The roots of the polynomial, kept in sync with `coefficients`.
     pub roots: Vec<RootConfig>,
+    /// Coefficients of the polynomial in ascending-power form, i.e. the
+    /// element at index `i` is the coefficient of `z^i`. Kept in sync with
+    /// `roots`.
+    pub coefficients: Vec<DVec2>,
+    /// A function that is iterated instead of the polynomial. The roots are
+    /// then only used for coloring, and are found numerically.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub function: Option<Function>,
     pub camera: CameraConfig,
 }
 
 impl Config {
+    /// Loads a preset from a file.
+    ///
+    /// The format is chosen by the file extension: `.json` files are parsed as
+    /// JSON, anything else as TOML. Fields missing from the file keep their
+    /// default values.
+    ///
+    /// If the preset has coefficients but no roots, the roots are computed
+    /// from them. Otherwise, the coefficients are computed from the roots. If
+    /// the preset has a function but no roots, the roots of the function are
+    /// found around the camera.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("failed to read preset {}", path.display()))?;
+        let parse = || -> anyhow::Result<(Self, PolynomialKeys)> {
+            Ok(if is_json(path) {
+                (
+                    serde_json::from_str(&contents)?,
+                    serde_json::from_str(&contents)?,
+                )
+            } else {
+                (toml::from_str(&contents)?, toml::from_str(&contents)?)
+            })
+        };
+        let (mut config, keys) =
+            parse().with_context(|| format!("failed to parse preset {}", path.display()))?;
+        config
+            .complete(keys)
+            .with_context(|| format!("invalid preset {}", path.display()))?;
+        Ok(config)
+    }
+
+    /// Deserializes a config that is nested in another file, completing it
+    /// like [`Config::load`].
+    pub fn deserialize_preset<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Self, D::Error> {
+        // Buffered, since the polynomial keys are read separately.
+        let value = serde_json::Value::deserialize(deserializer)?;
+        let keys = PolynomialKeys::deserialize(&value).map_err(de::Error::custom)?;
+        let mut config = Self::deserialize(value).map_err(de::Error::custom)?;
+        config.complete(keys).map_err(de::Error::custom)?;
+        Ok(config)
+    }
+
+    /// Computes whatever the preset doesn't specify of the roots and the
+    /// coefficients, see [`Config::load`].
+    fn complete(&mut self, keys: PolynomialKeys) -> anyhow::Result<()> {
+        self.check_finite()?;
+        for root in &mut self.roots {
+            root.multiplicity = root.multiplicity.clamp(1, MAX_MULTIPLICITY);
+        }
+        if self.function.is_some() && keys.roots.is_none() {
+            self.find_function_roots();
+        } else if keys.coefficients.is_some() && keys.roots.is_none() {
+            let coefficients = self.coefficients.clone();
+            self.set_coefficients(&coefficients);
+        } else {
+            self.update_coefficients();
+        }
+        // The roots may have been computed from the coefficients.
+        self.check_finite()
+    }
+
+    /// Checks that the parameters of the iteration are finite, which presets
+    /// don't guarantee since TOML has `inf` and `nan`. The camera zoom and
+    /// the epsilon also need to be positive.
+    fn check_finite(&self) -> anyhow::Result<()> {
+        ensure!(
+            self.camera.zoom.is_finite() && self.camera.zoom > 0.0,
+            "camera zoom {} is not a positive number",
+            self.camera.zoom
+        );
+        ensure!(
+            self.epsilon.is_finite() && self.epsilon > 0.0,
+            "epsilon {} is not a positive number",
+            self.epsilon
+        );
+        ensure!(
+            self.relaxation.is_finite(),
+            "relaxation {} is not finite",
+            self.relaxation
+        );
+        ensure!(
+            self.nova_c.is_finite(),
+            "nova_c {} is not finite",
+            self.nova_c
+        );
+        for (i, root) in self.roots.iter().enumerate() {
+            ensure!(
+                root.position.is_finite(),
+                "root {} at {} is not finite",
+                i + 1,
+                root.position
+            );
+        }
+        ensure!(
+            self.coefficients
+                .iter()
+                .all(|coefficient| coefficient.is_finite()),
+            "coefficients are not finite"
+        );
+        Ok(())
+    }
+
+    /// Saves this config as a preset file, in the format selected by the file
+    /// extension (see [`Config::load`]).
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let contents = if is_json(path) {
+            serde_json::to_string_pretty(self)?
+        } else {
+            // Round-trip through `toml::Value` so that tables are emitted after
+            // plain values, regardless of field order.
+            toml::to_string(&toml::Value::try_from(self)?)?
+        };
+        fs::write(path, contents)
+            .with_context(|| format!("failed to write preset {}", path.display()))?;
+        Ok(())
+    }
+
     pub fn apply(&mut self, event: &ConfigChangeEvent) {
-        match event {
-            &ConfigChangeEvent::NumIterations(v) => {
+        match *event {
+            ConfigChangeEvent::NumIterations(v) => {
                 self.num_iterations = v;
             }
-            &ConfigChangeEvent::AddRoot => {
+            ConfigChangeEvent::Method(v) => {
+                self.method = v;
+            }
+            ConfigChangeEvent::HouseholderOrder(v) => {
+                self.householder_order = v;
+            }
+            ConfigChangeEvent::Relaxation(v) => {
+                self.relaxation = v;
+            }
+            ConfigChangeEvent::Nova(v) => {
+                self.nova = v;
+            }
+            ConfigChangeEvent::NovaC(v) => {
+                self.nova_c = v;
+            }
+            ConfigChangeEvent::Epsilon(v) => {
+                self.epsilon = v;
+            }
+            ConfigChangeEvent::Coloring(v) => {
+                self.coloring = v;
+            }
+            ConfigChangeEvent::Basins(v) => {
+                self.basins = v;
+            }
+            ConfigChangeEvent::NonConvergenceColor(v) => {
+                self.non_convergence_color = v;
+            }
+            ConfigChangeEvent::MaxCyclePeriod(v) => {
+                self.max_cycle_period = v;
+            }
+            ConfigChangeEvent::CycleColor(v) => {
+                self.cycle_color = v;
+            }
+            ConfigChangeEvent::AddRoot => {
                 self.roots.push(Default::default());
+                self.update_coefficients();
+            }
+            ConfigChangeEvent::RemoveRoot { index } => {
+                if index < self.roots.len() {
+                    self.roots.remove(index);
+                    self.update_coefficients();
+                }
+            }
+            ConfigChangeEvent::DuplicateRoot { index } => {
+                if let Some(root) = self.roots.get(index) {
+                    let mut copy = root.clone();
+                    copy.position.x += DUPLICATE_OFFSET;
+                    self.roots.insert(index + 1, copy);
+                    self.update_coefficients();
+                }
             }
-            &ConfigChangeEvent::RemoveRoot { index } => {
-                self.roots.remove(index);
+            ConfigChangeEvent::MoveRoot { from, to } => {
+                // The order doesn't change the polynomial, only which root
+                // comes first.
+                if from < self.roots.len() && to < self.roots.len() {
+                    let root = self.roots.remove(from);
+                    self.roots.insert(to, root);
+                }
             }
-            &ConfigChangeEvent::RootPosition { index, position } => {
+            ConfigChangeEvent::RootPosition { index, position } => {
                 if let Some(root) = self.roots.get_mut(index) {
                     root.position = position;
+                    self.update_coefficients();
                 }
             }
-            &ConfigChangeEvent::RootColor { index, color } => {
+            ConfigChangeEvent::RootMultiplicity {
+                index,
+                multiplicity,
+            } => {
+                if let Some(root) = self.roots.get_mut(index) {
+                    root.multiplicity = multiplicity.clamp(1, MAX_MULTIPLICITY);
+                    self.update_coefficients();
+                }
+            }
+            ConfigChangeEvent::Coefficients(ref v) => {
+                self.set_coefficients(v);
+            }
+            ConfigChangeEvent::Function(ref v) => {
+                self.function = v.clone();
+                self.find_function_roots();
+            }
+            ConfigChangeEvent::FindFunctionRoots => {
+                self.find_function_roots();
+            }
+            ConfigChangeEvent::RootColor { index, color } => {
                 if let Some(root) = self.roots.get_mut(index) {
                     root.color = color;
                 }
             }
-            &ConfigChangeEvent::CameraPosition(v) => {
-                self.camera.position = v;
+            ConfigChangeEvent::CameraPosition(ref v) => {
+                self.camera.position = v.clone();
             }
-            &ConfigChangeEvent::CameraZoom(v) => {
+            ConfigChangeEvent::CameraZoom(v) => {
                 self.camera.zoom = v;
             }
         }
     }
+
+    /// The coefficients of the polynomial, divided by the leading coefficient.
+    ///
+    /// This is what the renderers use, since the factored form that they
+    /// evaluate with the roots always has a leading coefficient of 1.
+    pub fn polynomial(&self) -> Vec<Complex64> {
+        let coefficients: Vec<Complex64> =
+            self.coefficients.iter().map(|&c| to_complex(c)).collect();
+        match coefficients.last() {
+            Some(&leading) if leading != Complex64::new(0.0, 0.0) => {
+                coefficients.iter().map(|&c| c / leading).collect()
+            }
+            _ => poly::expand_roots(self.root_factors()),
+        }
+    }
+
+    /// The roots, each repeated as often as its multiplicity.
+    pub fn root_factors(&self) -> Vec<Complex64> {
+        self.roots
+            .iter()
+            .flat_map(|root| {
+                let position = to_complex(root.position.as_dvec2());
+                let multiplicity = root.multiplicity.clamp(1, MAX_MULTIPLICITY);
+                std::iter::repeat_n(position, multiplicity as usize)
+            })
+            .collect()
+    }
+
+    /// Recomputes the coefficients from the roots, keeping the leading
+    /// coefficient.
+    pub fn update_coefficients(&mut self) {
+        let leading = self
+            .coefficients
+            .last()
+            .map(|&c| to_complex(c))
+            .filter(|&c| c != Complex64::new(0.0, 0.0))
+            .unwrap_or(Complex64::new(1.0, 0.0));
+        self.coefficients = poly::expand_roots(self.root_factors())
+            .into_iter()
+            .map(|c| from_complex(c * leading))
+            .collect();
+    }
+
+    /// Replaces the coefficients, and finds the roots of the new polynomial.
+    ///
+    /// The previous roots are used as starting points, so the roots keep their
+    /// colors as long as the polynomial changes gradually, and additional roots
+    /// get colors spread around the hue circle. Roots that are found at the
+    /// same point become a single root with a higher multiplicity. A zero
+    /// polynomial is ignored, since it has no roots to show.
+    pub fn set_coefficients(&mut self, coefficients: &[DVec2]) {
+        let mut coefficients: Vec<Complex64> =
+            coefficients.iter().map(|&c| to_complex(c)).collect();
+        poly::trim(&mut coefficients);
+        if coefficients.is_empty() {
+            return;
+        }
+        let roots = poly::find_roots(&coefficients, &self.root_factors());
+        self.roots = poly::group_roots(&coefficients, &roots)
+            .into_iter()
+            .enumerate()
+            .map(|(i, (root, multiplicity))| RootConfig {
+                position: from_complex(root).as_vec2(),
+                multiplicity: multiplicity as u32,
+                color: self.roots.get(i).map_or_else(|| hue_color(i), |r| r.color),
+            })
+            .collect();
+        self.coefficients = coefficients.into_iter().map(from_complex).collect();
+    }
+
+    /// Replaces the roots with the roots of `function` that are found around
+    /// the camera, if there is a function.
+    ///
+    /// The search covers twice the area that is visible at the current zoom.
+    /// The roots keep their colors by index, and the ones beyond the previous
+    /// roots get colors spread around the hue circle.
+    pub fn find_function_roots(&mut self) {
+        let function = match &self.function {
+            Some(function) => function,
+            None => return,
+        };
+        let center = to_complex(self.camera.position.to_dvec2());
+        let radius = 2.0 / self.camera.zoom;
+        self.roots = function
+            .find_roots(center, radius)
+            .into_iter()
+            .enumerate()
+            .map(|(i, root)| RootConfig {
+                position: from_complex(root).as_vec2(),
+                multiplicity: 1,
+                color: self.roots.get(i).map_or_else(|| hue_color(i), |r| r.color),
+            })
+            .collect();
+        self.update_coefficients();
+    }
+}
+
+/// A saturated color for the root at `index`, where the hues of consecutive
+/// indices are spaced by the golden angle so that they never repeat.
+pub(crate) fn hue_color(index: usize) -> Vec4 {
+    let hue = (index as f32 * 0.618_034).fract() * 6.0;
+    let channel = |offset: f32| (((hue + offset) % 6.0 - 3.0).abs() - 1.0).clamp(0.0, 1.0);
+    Vec4::new(channel(0.0), channel(4.0), channel(2.0), 1.0)
+}
+
+/// The polynomial fields that a preset file specifies, to know which
+/// representation to compute the other one from.
+#[derive(Deserialize)]
+struct PolynomialKeys {
+    roots: Option<IgnoredAny>,
+    coefficients: Option<IgnoredAny>,
+}
+
+fn to_complex(v: DVec2) -> Complex64 {
+    Complex64::new(v.x, v.y)
+}
+
+fn from_complex(c: Complex64) -> DVec2 {
+    DVec2::new(c.re, c.im)
+}
+
+pub(crate) fn is_json(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("json"))
 }
 
 impl Default for Config {
     fn default() -> Self {
-        Self {
+        let mut config = Self {
             num_iterations: 30,
+            method: Default::default(),
+            householder_order: 3,
+            relaxation: Vec2::new(1.0, 0.0),
+            nova: Default::default(),
+            nova_c: Vec2::new(0.1, 0.0),
+            epsilon: 1e-4,
+            coloring: Default::default(),
+            basins: Default::default(),
+            non_convergence_color: Vec4::new(0.0, 0.0, 0.0, 1.0),
+            max_cycle_period: 0,
+            cycle_color: Vec4::new(0.5, 0.5, 0.5, 1.0),
             roots: vec![
                 RootConfig {
                     position: Vec2::new(0.5, 0.0),
+                    multiplicity: 1,
                     color: Vec4::new(0.0, 0.75, 0.0, 1.0),
                 },
                 RootConfig {
                     position: Vec2::new(-0.5, 0.0),
+                    multiplicity: 1,
                     color: Vec4::new(0.0, 0.0, 1.0, 0.0),
                 },
             ],
+            coefficients: Vec::new(),
+            function: None,
             camera: Default::default(),
+        };
+        config.update_coefficients();
+        config
+    }
+}
+
+/// The iteration that is used to find the roots.
+///
+/// The discriminants match the `METHOD_*` constants in `fractal.wgsl`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Method {
+    /// `z - p / p'`
+    #[default]
+    Newton = 0,
+    /// `z - m p / p'`, where `m` is the multiplicity of the nearest root. This
+    /// restores the quadratic convergence of Newton's method to multiple
+    /// roots.
+    ModifiedNewton = 5,
+    /// `z - 2 p p' / (2 p'^2 - p p'')`
+    Halley = 1,
+    /// `z - p p' / (p'^2 - p p'')`, which converges quickly to multiple roots
+    /// as well.
+    Schroder = 2,
+    /// `z - (p / p') (1 + p p'' / (2 p'^2))`
+    Chebyshev = 3,
+    /// `z + d (1/p)^(d-1) / (1/p)^(d)`, where `d` is the order. Order 1 is
+    /// Newton's method and order 2 is Halley's method.
+    Householder = 4,
+}
+
+impl Method {
+    pub const ALL: [Self; 6] = [
+        Self::Newton,
+        Self::ModifiedNewton,
+        Self::Halley,
+        Self::Schroder,
+        Self::Chebyshev,
+        Self::Householder,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Newton => "Newton",
+            Self::ModifiedNewton => "Newton (multiplicity)",
+            Self::Halley => "Halley",
+            Self::Schroder => "Schröder",
+            Self::Chebyshev => "Chebyshev",
+            Self::Householder => "Householder",
+        }
+    }
+
+    /// The number of derivatives of the polynomial that the method needs.
+    pub fn order(&self, householder_order: u32) -> u32 {
+        match self {
+            Self::Newton | Self::ModifiedNewton => 1,
+            Self::Halley | Self::Schroder | Self::Chebyshev => 2,
+            Self::Householder => householder_order.clamp(1, MAX_HOUSEHOLDER_ORDER),
+        }
+    }
+}
+
+/// Variants of the Nova fractal, where each iteration moves `z` to
+/// `z - a * p(z) / p'(z) + c`, or the equivalent with another [`Method`].
+///
+/// The discriminants match the `NOVA_*` constants in `fractal.wgsl`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Nova {
+    /// No constant is added, the plain root-finding iteration.
+    #[default]
+    Off = 0,
+    /// `c` is the point of the pixel, and the iteration starts at the first
+    /// root, which is a critical point of Newton's method.
+    Mandelbrot = 1,
+    /// `c` is fixed, and the iteration starts at the point of the pixel.
+    Julia = 2,
+}
+
+impl Nova {
+    pub const ALL: [Self; 3] = [Self::Off, Self::Mandelbrot, Self::Julia];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Off => "Off",
+            Self::Mandelbrot => "Mandelbrot",
+            Self::Julia => "Julia",
+        }
+    }
+}
+
+/// How the basin of each root is shaded.
+///
+/// The discriminants match the `COLORING_*` constants in `fractal.wgsl`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Coloring {
+    /// Every point in a basin gets the color of its root.
+    #[default]
+    Flat = 0,
+    /// Brightness decreases with the number of iterations needed to converge.
+    IterationCount = 1,
+    /// Like `IterationCount`, but interpolated between iterations so that
+    /// there are no visible bands.
+    SmoothIteration = 2,
+    /// Brightness decreases with the distance to the root after the last
+    /// iteration, on a logarithmic scale.
+    ///
+    /// In this mode all iterations are performed, even after converging.
+    RootDistance = 3,
+}
+
+impl Coloring {
+    pub const ALL: [Self; 4] = [
+        Self::Flat,
+        Self::IterationCount,
+        Self::SmoothIteration,
+        Self::RootDistance,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Flat => "Flat",
+            Self::IterationCount => "Iteration count",
+            Self::SmoothIteration => "Smooth iteration",
+            Self::RootDistance => "Distance to root",
         }
     }
 }
 
+/// How the basins are told apart.
+///
+/// The discriminants match the `BASINS_*` constants in `fractal.wgsl`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Basins {
+    /// Every point gets the color of the root that is closest to where its
+    /// orbit ends.
+    #[default]
+    NearestRoot = 0,
+    /// The points where the orbits end are clustered into attractors first,
+    /// see [`crate::attractor`]. This doesn't need the roots, and also shows
+    /// the basins of attracting cycles, which would not converge otherwise.
+    Attractors = 1,
+}
+
+impl Basins {
+    pub const ALL: [Self; 2] = [Self::NearestRoot, Self::Attractors];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::NearestRoot => "Nearest root",
+            Self::Attractors => "Attractors",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct RootConfig {
     pub position: Vec2,
+    /// How many times the root is repeated in the polynomial, from 1 to
+    /// [`MAX_MULTIPLICITY`].
+    pub multiplicity: u32,
     pub color: Vec4,
 }
 
@@ -66,31 +616,316 @@ impl Default for RootConfig {
     fn default() -> Self {
         Self {
             position: Vec2::ZERO,
+            multiplicity: 1,
             color: Vec4::new(0.0, 0.0, 0.0, 1.0),
         }
     }
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct CameraConfig {
-    pub position: Vec2,
-    pub zoom: f32,
+    /// Stored with arbitrary precision, so that the camera can zoom in further
+    /// than any floating point type can resolve.
+    pub position: PrecisePoint,
+    pub zoom: f64,
+}
+
+impl CameraConfig {
+    /// The region of the complex plane that is visible through the camera, on
+    /// an image of size `image_size`.
+    ///
+    /// The center of the region is rounded to `f64`, which is good enough
+    /// unless the region [is not resolved](Region::is_resolved_by) by `f64`.
+    ///
+    /// The shorter side of the image spans `2 / zoom` units, and the longer
+    /// side is extended to match the aspect ratio. That way, the square of
+    /// side `2 / zoom` around the camera position is always fully visible,
+    /// and circles stay round at any image size.
+    pub fn region(&self, image_size: Vec2) -> Region {
+        // An empty image, like the surface of a minimized window, has no
+        // aspect ratio to keep.
+        let aspect = if image_size.x > 0.0 && image_size.y > 0.0 {
+            image_size.x as f64 / image_size.y as f64
+        } else {
+            1.0
+        };
+        Region {
+            center: self.position.to_dvec2(),
+            half_size: DVec2::new(aspect.max(1.0), aspect.recip().max(1.0)) / self.zoom,
+        }
+    }
 }
 
 impl Default for CameraConfig {
     fn default() -> Self {
         Self {
-            position: Vec2::ZERO,
+            position: Default::default(),
             zoom: 1.0,
         }
     }
 }
 
+/// An axis-aligned rectangle on the complex plane, which is mapped onto the
+/// whole render target.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Region {
+    pub center: DVec2,
+    /// Half of the width and height of the rectangle.
+    pub half_size: DVec2,
+}
+
+impl Region {
+    /// Converts a position in pixels, measured from the top-left corner of an
+    /// image of size `image_size` showing this region, to a point on the
+    /// complex plane.
+    pub fn pixel_to_point(&self, pixel: Vec2, image_size: Vec2) -> DVec2 {
+        self.center + self.pixel_to_offset(pixel, image_size)
+    }
+
+    /// Like [`Region::pixel_to_point`], but relative to the center of the
+    /// region. This stays exact even if the center is not resolved.
+    pub fn pixel_to_offset(&self, pixel: Vec2, image_size: Vec2) -> DVec2 {
+        let pixel = pixel.as_dvec2();
+        let image_size = image_size.as_dvec2();
+        let clip = DVec2::new(
+            2.0 * pixel.x / image_size.x - 1.0,
+            1.0 - 2.0 * pixel.y / image_size.y,
+        );
+        clip * self.half_size
+    }
+
+    /// The inverse of [`Region::pixel_to_point`].
+    pub fn point_to_pixel(&self, point: DVec2, image_size: Vec2) -> Vec2 {
+        let clip = (point - self.center) / self.half_size;
+        (DVec2::new(1.0 + clip.x, 1.0 - clip.y) * 0.5 * image_size.as_dvec2()).as_vec2()
+    }
+
+    /// The width and height of a pixel on an image of size `image_size`
+    /// showing this region.
+    pub fn pixel_size(&self, image_size: Vec2) -> DVec2 {
+        2.0 * self.half_size / image_size.as_dvec2()
+    }
+
+    /// Whether a floating point type with machine epsilon `epsilon` is precise
+    /// enough to tell the pixels apart on an image of size `image_size`
+    /// showing this region.
+    pub fn is_resolved_by(&self, epsilon: f64, image_size: Vec2) -> bool {
+        let pixel_size = self.pixel_size(image_size).min_element();
+        // The iteration moves points towards the roots, which are usually
+        // around the unit circle, so the spacing of representable values is
+        // never finer than it is at 1.
+        let magnitude = self.center.abs().max_element().max(1.0);
+        pixel_size >= MIN_PIXEL_ULPS * epsilon * magnitude
+    }
+
+    /// Returns the part of this region that covers the pixels
+    /// `x..x + width, y..y + height` of an image with size
+    /// `image_width x image_height`.
+    ///
+    /// Pixel rows are counted from the top, i.e. from the largest imaginary
+    /// part. On the CPU, rendering each tile of an image separately samples
+    /// exactly the same points as rendering the whole image at once. The GPU
+    /// renderer scales each tile in `f32`, so its samples can differ by
+    /// rounding.
+    pub fn tile(
+        &self,
+        image_width: u32,
+        image_height: u32,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    ) -> Region {
+        let pixel_size = 2.0 * self.half_size / DVec2::new(image_width as f64, image_height as f64);
+        let top_left = self.center + DVec2::new(-self.half_size.x, self.half_size.y);
+        let tile_center = DVec2::new(
+            x as f64 + 0.5 * width as f64,
+            y as f64 + 0.5 * height as f64,
+        );
+        Region {
+            center: top_left + DVec2::new(1.0, -1.0) * tile_center * pixel_size,
+            half_size: 0.5 * DVec2::new(width as f64, height as f64) * pixel_size,
+        }
+    }
+}
+
 pub enum ConfigChangeEvent {
     NumIterations(u32),
+    Method(Method),
+    HouseholderOrder(u32),
+    Relaxation(Vec2),
+    Nova(Nova),
+    NovaC(Vec2),
+    Epsilon(f32),
+    Coloring(Coloring),
+    Basins(Basins),
+    NonConvergenceColor(Vec4),
+    MaxCyclePeriod(u32),
+    CycleColor(Vec4),
     AddRoot,
-    RemoveRoot { index: usize },
-    RootPosition { index: usize, position: Vec2 },
-    RootColor { index: usize, color: Vec4 },
-    CameraPosition(Vec2),
-    CameraZoom(f32),
+    RemoveRoot {
+        index: usize,
+    },
+    /// Inserts a copy of a root right after it, moved by
+    /// [`DUPLICATE_OFFSET`] so that it doesn't make a double root.
+    DuplicateRoot {
+        index: usize,
+    },
+    /// Moves the root at index `from` to index `to`, shifting the ones in
+    /// between.
+    MoveRoot {
+        from: usize,
+        to: usize,
+    },
+    RootPosition {
+        index: usize,
+        position: Vec2,
+    },
+    RootMultiplicity {
+        index: usize,
+        multiplicity: u32,
+    },
+    /// Replaces the coefficients of the polynomial, which moves the roots.
+    Coefficients(Vec<DVec2>),
+    /// Iterates a function instead of the polynomial, or the polynomial again
+    /// for `None`. This finds the roots of the function.
+    Function(Option<Function>),
+    /// Finds the roots of the function again, around the current camera.
+    FindFunctionRoots,
+    RootColor {
+        index: usize,
+        color: Vec4,
+    },
+    CameraPosition(PrecisePoint),
+    CameraZoom(f64),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reads a config from TOML, completing it like [`Config::load`].
+    fn from_toml(contents: &str) -> anyhow::Result<Config> {
+        Ok(Config::deserialize_preset(&mut toml::Deserializer::new(
+            contents,
+        ))?)
+    }
+
+    #[test]
+    fn multiplicity_is_clamped() {
+        let mut config = Config::default();
+        config.apply(&ConfigChangeEvent::RootMultiplicity {
+            index: 0,
+            multiplicity: 1_000_000,
+        });
+        assert_eq!(config.roots[0].multiplicity, MAX_MULTIPLICITY);
+        assert_eq!(config.coefficients.len(), MAX_MULTIPLICITY as usize + 2);
+        config.apply(&ConfigChangeEvent::RootMultiplicity {
+            index: 0,
+            multiplicity: 0,
+        });
+        assert_eq!(config.roots[0].multiplicity, 1);
+
+        let config = from_toml(
+            "[[roots]]\n\
+             position = [0.5, 0.0]\n\
+             multiplicity = 1000000\n",
+        )
+        .unwrap();
+        assert_eq!(config.roots[0].multiplicity, MAX_MULTIPLICITY);
+    }
+
+    #[test]
+    fn out_of_range_multiplicity_keeps_the_coefficients() {
+        let mut config = Config::default();
+        // Roots at +-sqrt(0.1), which `f32` positions can't hold exactly.
+        let coefficients = vec![DVec2::new(-0.1, 0.0), DVec2::ZERO, DVec2::X];
+        config.apply(&ConfigChangeEvent::Coefficients(coefficients.clone()));
+        config.apply(&ConfigChangeEvent::RootMultiplicity {
+            index: config.roots.len(),
+            multiplicity: 2,
+        });
+        assert_eq!(config.coefficients, coefficients);
+    }
+
+    #[test]
+    fn out_of_range_root_indices_are_ignored() {
+        let mut config = Config::default();
+        let positions = |config: &Config| -> Vec<Vec2> {
+            config.roots.iter().map(|root| root.position).collect()
+        };
+        let roots = positions(&config);
+        let coefficients = config.coefficients.clone();
+        let len = roots.len();
+        for event in &[
+            ConfigChangeEvent::RemoveRoot { index: len },
+            ConfigChangeEvent::MoveRoot { from: len, to: 0 },
+            ConfigChangeEvent::MoveRoot { from: 0, to: len },
+            ConfigChangeEvent::DuplicateRoot { index: len },
+            ConfigChangeEvent::RootPosition {
+                index: len,
+                position: Vec2::ONE,
+            },
+        ] {
+            config.apply(event);
+            assert_eq!(positions(&config), roots);
+            assert_eq!(config.coefficients, coefficients);
+        }
+
+        config.apply(&ConfigChangeEvent::MoveRoot {
+            from: 0,
+            to: len - 1,
+        });
+        assert_eq!(positions(&config), [roots[1], roots[0]]);
+        config.apply(&ConfigChangeEvent::RemoveRoot { index: len - 1 });
+        assert_eq!(positions(&config), [roots[1]]);
+    }
+
+    #[test]
+    fn new_roots_from_coefficients_get_distinct_colors() {
+        let mut config = Config::default();
+        let colors: Vec<_> = config.roots.iter().map(|root| root.color).collect();
+        let coefficients = poly::parse("z^5 - 3z^2 + 1").unwrap();
+        let coefficients: Vec<_> = coefficients.into_iter().map(from_complex).collect();
+        config.apply(&ConfigChangeEvent::Coefficients(coefficients));
+        assert_eq!(config.roots.len(), 5);
+        for (i, root) in config.roots.iter().enumerate() {
+            assert_ne!(root.color, config.non_convergence_color);
+            if let Some(&color) = colors.get(i) {
+                assert_eq!(root.color, color);
+            }
+        }
+    }
+
+    #[test]
+    fn non_finite_presets_are_rejected() {
+        assert!(from_toml("[[roots]]\nposition = [nan, 0.0]").is_err());
+        assert!(from_toml("[[roots]]\nposition = [0.5, inf]").is_err());
+        assert!(from_toml("[[roots]]\nposition = [0.5, 0.0]").is_ok());
+        assert!(from_toml("relaxation = [inf, 0.0]").is_err());
+        assert!(from_toml("relaxation = [0.5, 0.0]").is_ok());
+        assert!(from_toml("nova_c = [0.0, nan]").is_err());
+        assert!(from_toml("coefficients = [[1.0, 0.0], [-inf, 0.0], [1.0, 0.0]]").is_err());
+        for zoom in &["inf", "nan", "0.0", "-2.0"] {
+            assert!(from_toml(&format!("[camera]\nzoom = {}", zoom)).is_err());
+        }
+        assert!(from_toml("epsilon = 0.0").is_err());
+        assert!(from_toml("epsilon = nan").is_err());
+        assert!(from_toml("[camera]\nzoom = 1e300").is_ok());
+    }
+
+    #[test]
+    fn region_keeps_the_aspect_ratio() {
+        let camera = CameraConfig {
+            zoom: 2.0,
+            ..Default::default()
+        };
+        let half_size = |width, height| camera.region(Vec2::new(width, height)).half_size;
+        assert_eq!(half_size(200.0, 100.0), DVec2::new(1.0, 0.5));
+        assert_eq!(half_size(100.0, 200.0), DVec2::new(0.5, 1.0));
+        // Like the surface of a minimized window.
+        assert_eq!(half_size(200.0, 0.0), DVec2::new(0.5, 0.5));
+        assert_eq!(half_size(0.0, 0.0), DVec2::new(0.5, 0.5));
+    }
 }
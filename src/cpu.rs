@@ -0,0 +1,659 @@
+//! CPU reference implementation of the fractal renderer.
+//!
+//! This mirrors the logic of `fractal.wgsl` (the `poly`/`grad` functions or
+//! the generated function code, the root-finding methods and the nearest-root
+//! coloring), but runs on the CPU in double precision. It does
+//! not need a window or graphics adapter, so it serves as the ground truth for
+//! the GPU renderer and as a fallback for offline exports.
+
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+use glam::{DVec2, Vec2, Vec4};
+use num_complex::Complex64;
+use rayon::prelude::*;
+
+use crate::{
+    attractor::{self, Attractor, Sample},
+    config::{Basins, Coloring, Config, Method, Nova, Region, MAX_HOUSEHOLDER_ORDER},
+    expr::Taylor,
+    image::Image,
+};
+
+/// Renders `config` into a new image of the given size.
+///
+/// Rows are rendered in parallel.
+pub fn render(config: &Config, width: u32, height: u32) -> Image {
+    render_region(
+        config,
+        &config.camera.region(Vec2::new(width as f32, height as f32)),
+        width,
+        height,
+    )
+}
+
+/// Renders the given region of the complex plane into a new image, ignoring
+/// the camera in `config`.
+///
+/// With [`Basins::Attractors`], the attractors are found around the camera
+/// first, like on the GPU.
+pub fn render_region(config: &Config, region: &Region, width: u32, height: u32) -> Image {
+    let mut params = Params::new(config, region);
+    if config.basins == Basins::Attractors {
+        params.set_attractors(config, &attractor::find(config, &attractor_samples(config)));
+    }
+    render_pixels(width, height, |clip| {
+        params.color(&mut params.start(params.grid_position(clip)))
+    })
+}
+
+/// Renders an image of the given size, where `color` gives the color at a
+/// point in clip space.
+pub(crate) fn render_pixels(
+    width: u32,
+    height: u32,
+    color: impl Fn(DVec2) -> Vec4 + Sync,
+) -> Image {
+    let mut data = vec![0; width as usize * height as usize * 4];
+    data.par_chunks_mut(width as usize * 4)
+        .enumerate()
+        .for_each(|(y, row)| {
+            for (x, pixel) in row.chunks_exact_mut(4).enumerate() {
+                // Sample at the pixel center, in the same [-1, 1] clip space
+                // coordinates as the vertex shader (with y pointing up).
+                let clip = DVec2::new(
+                    2.0 * (x as f64 + 0.5) / width as f64 - 1.0,
+                    1.0 - 2.0 * (y as f64 + 0.5) / height as f64,
+                );
+                pixel.copy_from_slice(&to_rgba8(color(clip)));
+            }
+        });
+    Image {
+        width,
+        height,
+        data,
+    }
+}
+
+/// The final positions of the orbits on the grid of
+/// [`attractor::sample_region`], row by row from the top.
+pub fn attractor_samples(config: &Config) -> Vec<Sample> {
+    let params = Params::new(config, &attractor::sample_region(config));
+    let size = attractor::GRID_SIZE;
+    (0..size * size)
+        .into_par_iter()
+        .map(|i| {
+            let (x, y) = (i % size, i / size);
+            let clip = DVec2::new(
+                2.0 * (x as f64 + 0.5) / size as f64 - 1.0,
+                1.0 - 2.0 * (y as f64 + 0.5) / size as f64,
+            );
+            let mut orbit = params.start(params.grid_position(clip));
+            let converged = matches!(params.iterate(&mut orbit), Outcome::Converged { .. });
+            Sample {
+                position: orbit.position(),
+                converged,
+            }
+        })
+        .collect()
+}
+
+/// Converts a color to 8-bit components, the same way as writing to an
+/// `Rgba8Unorm` render target.
+fn to_rgba8(color: Vec4) -> [u8; 4] {
+    let c = (color.clamp(Vec4::ZERO, Vec4::ONE) * 255.0).round();
+    [c.x as u8, c.y as u8, c.z as u8, c.w as u8]
+}
+
+/// The iteration of a single point.
+pub(crate) trait Orbit {
+    /// Performs one iteration, returning the step that was taken.
+    fn step(&mut self) -> Complex64;
+
+    /// The point after the iterations so far.
+    fn position(&self) -> Complex64;
+}
+
+/// An orbit that is iterated directly in double precision.
+pub(crate) struct Direct<'a> {
+    pub params: &'a Params,
+    pub position: Complex64,
+    /// The constant that is added in each iteration, see [`Nova`].
+    pub c: Complex64,
+}
+
+impl Orbit for Direct<'_> {
+    fn step(&mut self) -> Complex64 {
+        let step = self.params.step(self.position) - self.c;
+        self.position -= step;
+        step
+    }
+
+    fn position(&self) -> Complex64 {
+        self.position
+    }
+}
+
+/// The parameters of the shader, converted to double precision.
+pub(crate) struct Params {
+    num_iterations: u32,
+    method: Method,
+    /// The number of Taylor coefficients that `method` needs, minus one.
+    order: usize,
+    relaxation: Complex64,
+    nova: Nova,
+    nova_c: Complex64,
+    epsilon: f64,
+    coloring: Coloring,
+    non_convergence_color: Vec4,
+    /// The longest period of the cycles that [`Params::iterate`] detects, or
+    /// 0 to not look for cycles.
+    max_cycle_period: u32,
+    cycle_color: Vec4,
+    camera_position: Complex64,
+    camera_scale: DVec2,
+    roots: Vec<Root>,
+    /// The coefficients of the expanded polynomial, where every root is
+    /// repeated by its multiplicity.
+    coefficients: Vec<Complex64>,
+    /// The derivatives of the function that is iterated instead of the
+    /// polynomial, if any.
+    function: Option<Taylor>,
+    basins: Basins,
+    /// The points of the attractors, for [`Basins::Attractors`].
+    attractors: Vec<AttractorPoint>,
+    /// The distance within which an orbit that did not converge is considered
+    /// to be on a cycle, see [`attractor::radius`].
+    attractor_radius: f64,
+}
+
+struct Root {
+    position: Complex64,
+    color: Vec4,
+    multiplicity: f64,
+}
+
+struct AttractorPoint {
+    position: Complex64,
+    color: Vec4,
+    period: usize,
+}
+
+/// How an orbit ended, see [`Params::iterate`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Outcome {
+    /// The step size dropped below the tolerance, after a number of
+    /// iterations that is given both as a whole number and interpolated
+    /// between iterations.
+    Converged {
+        iterations: f64,
+        smooth_iterations: f64,
+    },
+    /// The orbit returned to an earlier point within the tolerance, which
+    /// means it is on a cycle of the given period.
+    Cycle { period: u32, iterations: f64 },
+    /// Neither happened within the maximum number of iterations, or the
+    /// orbit ended up at infinity or NaN.
+    NotConverged,
+}
+
+impl Params {
+    pub fn new(config: &Config, region: &Region) -> Self {
+        let roots: Vec<_> = config
+            .roots
+            .iter()
+            .map(|root| Root {
+                position: to_complex(root.position),
+                color: root.color,
+                multiplicity: root.multiplicity.max(1) as f64,
+            })
+            .collect();
+        let order = config.method.order(config.householder_order) as usize;
+        Self {
+            num_iterations: config.num_iterations,
+            method: config.method,
+            order,
+            relaxation: to_complex(config.relaxation),
+            nova: config.nova,
+            nova_c: to_complex(config.nova_c),
+            epsilon: config.epsilon as f64,
+            coloring: config.coloring,
+            non_convergence_color: config.non_convergence_color,
+            max_cycle_period: config.max_cycle_period,
+            cycle_color: config.cycle_color,
+            camera_position: Complex64::new(region.center.x, region.center.y),
+            camera_scale: region.half_size,
+            coefficients: config.polynomial(),
+            function: config
+                .function
+                .as_ref()
+                .map(|function| function.taylor(order)),
+            roots,
+            basins: config.basins,
+            attractors: Vec::new(),
+            attractor_radius: attractor::radius(config),
+        }
+    }
+
+    /// Sets the attractors that the basins are colored by with
+    /// [`Basins::Attractors`].
+    pub fn set_attractors(&mut self, config: &Config, attractors: &[Attractor]) {
+        self.attractor_radius = attractor::radius(config);
+        self.attractors = attractors
+            .iter()
+            .flat_map(|attractor| {
+                attractor
+                    .points
+                    .iter()
+                    .map(move |&position| AttractorPoint {
+                        position,
+                        color: attractor.color,
+                        period: attractor.period(),
+                    })
+            })
+            .collect();
+    }
+
+    fn grid_position(&self, clip: DVec2) -> Complex64 {
+        let offset = clip * self.camera_scale;
+        Complex64::new(offset.x, offset.y) + self.camera_position
+    }
+
+    /// The orbit of the point `pixel` in the rendered region.
+    pub fn start(&self, pixel: Complex64) -> Direct<'_> {
+        let (position, c) = match self.nova {
+            Nova::Off => (pixel, Complex64::new(0.0, 0.0)),
+            Nova::Mandelbrot => (self.critical_point(), pixel),
+            Nova::Julia => (pixel, self.nova_c),
+        };
+        Direct {
+            params: self,
+            position,
+            c,
+        }
+    }
+
+    /// The starting point of [`Nova::Mandelbrot`].
+    pub fn critical_point(&self) -> Complex64 {
+        self.roots
+            .first()
+            .map_or(Complex64::new(0.0, 0.0), |root| root.position)
+    }
+
+    /// The root that is closest to `z`, if there are any.
+    fn nearest_root(&self, z: Complex64) -> Option<&Root> {
+        self.roots
+            .iter()
+            .min_by(|a, b| (z - a.position).norm().total_cmp(&(z - b.position).norm()))
+    }
+
+    /// The attractor point that is closest to `z`, if there are any.
+    fn nearest_attractor(&self, z: Complex64) -> Option<&AttractorPoint> {
+        self.attractors
+            .iter()
+            .min_by(|a, b| (z - a.position).norm().total_cmp(&(z - b.position).norm()))
+    }
+
+    /// The factor that the step of the method at `z` is multiplied with,
+    /// besides the relaxation. This is the multiplicity of the nearest root for
+    /// [`Method::ModifiedNewton`], and 1 otherwise.
+    pub fn multiplicity_factor(&self, z: Complex64) -> f64 {
+        match (self.method, self.nearest_root(z)) {
+            (Method::ModifiedNewton, Some(root)) => root.multiplicity,
+            _ => 1.0,
+        }
+    }
+
+    /// The step of the root-finding method at `z`, i.e. the point is moved to
+    /// `z - step(z)`, plus the constant of the orbit.
+    pub fn step(&self, z: Complex64) -> Complex64 {
+        let mut taylor = [Complex64::new(0.0, 0.0); MAX_HOUSEHOLDER_ORDER as usize + 1];
+        let taylor = &mut taylor[..=self.order];
+        match &self.function {
+            Some(function) => function.evaluate(z, taylor),
+            None => {
+                if self.order > 1 {
+                    taylor_coefficients(&self.coefficients, &z, taylor);
+                }
+                taylor[0] = self.poly(z);
+                taylor[1] = self.grad(z);
+            }
+        }
+        match self.method {
+            Method::Newton => return self.relaxation * (taylor[0] / taylor[1]),
+            Method::ModifiedNewton => {
+                return self.relaxation * (self.multiplicity_factor(z) * (taylor[0] / taylor[1]))
+            }
+            _ => {}
+        }
+        // A zero denominator is left to turn into NaN, like on the GPU.
+        let step = method_step(self.method, taylor).unwrap_or(Complex64::new(f64::NAN, f64::NAN));
+        self.relaxation * step
+    }
+
+    /// Value of the polynomial at `z`, using the factored form.
+    fn poly(&self, z: Complex64) -> Complex64 {
+        self.roots
+            .iter()
+            .fold(Complex64::new(1.0, 0.0), |product, root| {
+                product * (z - root.position).powi(root.multiplicity as i32)
+            })
+    }
+
+    /// Derivative of the polynomial at `z`, using the ascending-powers form.
+    fn grad(&self, z: Complex64) -> Complex64 {
+        let mut sum = Complex64::new(0.0, 0.0);
+        let mut z_power = Complex64::new(1.0, 0.0);
+        for (i, &coefficient) in self.coefficients.iter().enumerate().skip(1) {
+            sum += i as f64 * coefficient * z_power;
+            z_power *= z;
+        }
+        sum
+    }
+
+    /// Runs the iterations of `orbit`, stopping early when it converges or
+    /// falls into a cycle.
+    pub fn iterate(&self, orbit: &mut impl Orbit) -> Outcome {
+        let mut iterations = self.num_iterations as f64;
+        let mut smooth_iterations = iterations;
+        let mut converged = false;
+        let mut last_step_size = 0.0;
+        // The point that the orbit is compared with to detect cycles, and the
+        // iteration that it was reached at. It moves along every
+        // `max_cycle_period` iterations, so that any cycle up to that period
+        // is caught once the orbit has settled on it.
+        let mut cycle_start = orbit.position();
+        let mut cycle_start_iteration = 0;
+        for i in 0..self.num_iterations {
+            let step = orbit.step();
+            let step_size = step.norm();
+            if !converged && step_size < self.epsilon {
+                converged = true;
+                iterations = (i + 1) as f64;
+                smooth_iterations = iterations;
+                if i > 0 {
+                    smooth_iterations =
+                        i as f64 + smooth_fraction(self.epsilon, last_step_size, step_size);
+                }
+                // Distance shading depends on the position after the last
+                // iteration, everything else can stop here.
+                if self.coloring != Coloring::RootDistance {
+                    break;
+                }
+            }
+            // Returning to an earlier point within the tolerance means the
+            // orbit is on a cycle. Fixed points converge before they get here.
+            if !converged && self.max_cycle_period > 0 {
+                let steps = i + 1 - cycle_start_iteration;
+                if (orbit.position() - cycle_start).norm() < self.epsilon {
+                    return Outcome::Cycle {
+                        period: steps,
+                        iterations: (i + 1) as f64,
+                    };
+                }
+                if steps == self.max_cycle_period {
+                    cycle_start = orbit.position();
+                    cycle_start_iteration = i + 1;
+                }
+            }
+            last_step_size = step_size;
+        }
+
+        // This also catches iterations that ended up at infinity or NaN, since
+        // the step size comparison is false for those.
+        if converged {
+            Outcome::Converged {
+                iterations,
+                smooth_iterations,
+            }
+        } else {
+            Outcome::NotConverged
+        }
+    }
+
+    /// The color of the point that `orbit` starts from.
+    pub fn color(&self, orbit: &mut impl Orbit) -> Vec4 {
+        let outcome = self.iterate(orbit);
+        let position = orbit.position();
+        let cycle_color = || match self.nearest_attractor(position) {
+            Some(attractor)
+                if attractor.period > 1
+                    && (position - attractor.position).norm() < self.attractor_radius =>
+            {
+                Some(attractor.color)
+            }
+            _ => None,
+        };
+        let (iterations, smooth_iterations) = match outcome {
+            Outcome::Converged {
+                iterations,
+                smooth_iterations,
+            } => (iterations, smooth_iterations),
+            Outcome::Cycle { iterations, .. } => {
+                // The cycle is only known if the attractors were clustered,
+                // otherwise all cycles share a color.
+                let color = cycle_color().unwrap_or(self.cycle_color);
+                let brightness = match self.coloring {
+                    Coloring::IterationCount | Coloring::SmoothIteration => {
+                        1.0 - iterations / self.num_iterations as f64
+                    }
+                    Coloring::Flat | Coloring::RootDistance => 1.0,
+                };
+                return (color.truncate() * brightness as f32).extend(color.w);
+            }
+            Outcome::NotConverged => {
+                // Without cycle detection, orbits that ended up close to an
+                // attracting cycle still get its color.
+                return cycle_color().unwrap_or(self.non_convergence_color);
+            }
+        };
+
+        let target = match self.basins {
+            Basins::NearestRoot => self
+                .nearest_root(position)
+                .map(|root| (root.color, root.position)),
+            Basins::Attractors => self
+                .nearest_attractor(position)
+                .map(|attractor| (attractor.color, attractor.position)),
+        };
+        let (color, target) = match target {
+            Some(target) => target,
+            None => return self.non_convergence_color,
+        };
+        let min_distance = (position - target).norm();
+
+        let brightness = match self.coloring {
+            Coloring::Flat => 1.0,
+            Coloring::IterationCount => 1.0 - iterations / self.num_iterations as f64,
+            Coloring::SmoothIteration => 1.0 - smooth_iterations / self.num_iterations as f64,
+            Coloring::RootDistance => (min_distance.ln() / self.epsilon.ln()).clamp(0.0, 1.0),
+        };
+        (color.truncate() * brightness as f32).extend(color.w)
+    }
+}
+
+/// Arithmetic needed by [`method_step`], for the different kinds of complex
+/// numbers that the renderers iterate with.
+pub(crate) trait Scalar:
+    Clone
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Mul<Output = Self>
+    + Div<Output = Self>
+    + Neg<Output = Self>
+{
+    /// Zero, with the same precision as `self`.
+    fn zero_like(&self) -> Self;
+
+    /// One, with the same precision as `self`.
+    fn one_like(&self) -> Self;
+
+    fn is_zero(&self) -> bool;
+}
+
+impl Scalar for Complex64 {
+    fn zero_like(&self) -> Self {
+        Complex64::new(0.0, 0.0)
+    }
+
+    fn one_like(&self) -> Self {
+        Complex64::new(1.0, 0.0)
+    }
+
+    fn is_zero(&self) -> bool {
+        *self == Complex64::new(0.0, 0.0)
+    }
+}
+
+/// Fills `taylor` with the normalized Taylor coefficients `p^(k)(z) / k!` of
+/// the polynomial with the ascending `coefficients` around `z`.
+///
+/// Uses Horner's method, which computes all the derivatives at once.
+pub(crate) fn taylor_coefficients<T: Scalar>(coefficients: &[T], z: &T, taylor: &mut [T]) {
+    for a in taylor.iter_mut() {
+        *a = z.zero_like();
+    }
+    let order = taylor.len() - 1;
+    for (i, coefficient) in coefficients.iter().rev().enumerate() {
+        for k in (1..=order.min(i)).rev() {
+            taylor[k] = taylor[k].clone() * z.clone() + taylor[k - 1].clone();
+        }
+        taylor[0] = taylor[0].clone() * z.clone() + coefficient.clone();
+    }
+}
+
+/// The step of `method`, without relaxation, from the normalized Taylor
+/// coefficients of the polynomial at the current point.
+///
+/// `taylor` needs `method.order()` + 1 coefficients, and is used as scratch
+/// space. Returns `None` if the step divides by zero.
+///
+/// For [`Method::ModifiedNewton`], this is only the Newton step, which still
+/// needs to be multiplied by [`Params::multiplicity_factor`].
+pub(crate) fn method_step<T: Scalar>(method: Method, taylor: &mut [T]) -> Option<T> {
+    let a0 = taylor[0].clone();
+    let a1 = taylor[1].clone();
+    let step = match method {
+        Method::Newton | Method::ModifiedNewton => checked_div(a0, a1)?,
+        Method::Halley => {
+            let a2 = taylor[2].clone();
+            checked_div(a0.clone() * a1.clone(), a1.clone() * a1 - a0 * a2)?
+        }
+        Method::Schroder => {
+            let a2 = taylor[2].clone();
+            let a0a2 = a0.clone() * a2;
+            checked_div(a0 * a1.clone(), a1.clone() * a1 - (a0a2.clone() + a0a2))?
+        }
+        Method::Chebyshev => {
+            let a2 = taylor[2].clone();
+            let a1_squared = a1.clone() * a1.clone();
+            checked_div(a0.clone() * (a1_squared.clone() + a0 * a2), a1_squared * a1)?
+        }
+        Method::Householder => householder_step(taylor)?,
+    };
+    Some(step)
+}
+
+/// The step of Householder's method, of order `taylor.len() - 1`.
+fn householder_step<T: Scalar>(taylor: &mut [T]) -> Option<T> {
+    let a0 = taylor[0].clone();
+    let order = taylor.len() - 1;
+    // The Taylor coefficients of `1 / p` are `b_0 = 1 / a_0` and
+    // `b_k = -(a_1 b_(k-1) + a_2 b_(k-2) + ... + a_k b_0) / a_0`. They are
+    // scaled by `a_0^(k+1)` here, which keeps them in range as `a_0`
+    // approaches zero, and turns the division into a multiplication of `a_k`
+    // by `a_0^(k-1)`.
+    let mut inverse = Vec::with_capacity(order + 1);
+    inverse.push(a0.one_like());
+    let mut a0_power = a0.one_like();
+    for k in 1..=order {
+        taylor[k] = taylor[k].clone() * a0_power.clone();
+        a0_power = a0_power * a0.clone();
+        let mut sum = a0.zero_like();
+        for j in 1..=k {
+            sum = sum + taylor[j].clone() * inverse[k - j].clone();
+        }
+        inverse.push(-sum);
+    }
+    checked_div(-(a0 * inverse[order - 1].clone()), inverse[order].clone())
+}
+
+fn checked_div<T: Scalar>(a: T, b: T) -> Option<T> {
+    if b.is_zero() {
+        None
+    } else {
+        Some(a / b)
+    }
+}
+
+/// Interpolates where between two consecutive steps the step size crossed the
+/// convergence tolerance `epsilon`, on a logarithmic scale. Returns a value in
+/// `[0, 1]`, where 1 means that `step_size` is right at the tolerance.
+fn smooth_fraction(epsilon: f64, last_step_size: f64, step_size: f64) -> f64 {
+    let last = last_step_size.ln();
+    let current = step_size.max(1e-300).ln();
+    ((epsilon.ln() - last) / (current - last)).clamp(0.0, 1.0)
+}
+
+fn to_complex(v: Vec2) -> Complex64 {
+    Complex64::new(v.x as f64, v.y as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pixel(image: &Image, x: u32, y: u32) -> [u8; 4] {
+        let i = (y * image.width + x) as usize * 4;
+        let mut pixel = [0; 4];
+        pixel.copy_from_slice(&image.data[i..i + 4]);
+        pixel
+    }
+
+    #[test]
+    fn default_config_splits_at_the_imaginary_axis() {
+        // The roots are at 0.5 (green) and -0.5 (blue).
+        let config = Config::default();
+        let image = render(&config, 64, 32);
+        for y in 0..image.height {
+            for x in 0..image.width {
+                let [_, green, blue, _] = pixel(&image, x, y);
+                if x < image.width / 2 {
+                    assert!(blue > 0 && green == 0, "pixel ({}, {}) is not blue", x, y);
+                } else {
+                    assert!(green > 0 && blue == 0, "pixel ({}, {}) is not green", x, y);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn tiles_match_the_whole_image() {
+        let mut config = Config::default();
+        config.roots[1].position = Vec2::new(-0.25, 0.5);
+        config.update_coefficients();
+        let (width, height) = (64, 32);
+        let region = config.camera.region(Vec2::new(width as f32, height as f32));
+        let whole = render_region(&config, &region, width, height);
+
+        let mut stitched = vec![0; whole.data.len()];
+        let (tile_width, tile_height) = (24, 12);
+        for y in (0..height).step_by(tile_height as usize) {
+            for x in (0..width).step_by(tile_width as usize) {
+                let (tile_width, tile_height) =
+                    (tile_width.min(width - x), tile_height.min(height - y));
+                let tile_region = region.tile(width, height, x, y, tile_width, tile_height);
+                let tile = render_region(&config, &tile_region, tile_width, tile_height);
+                for row in 0..tile_height {
+                    let start = ((y + row) * width + x) as usize * 4;
+                    let tile_row =
+                        &tile.data[(row * tile_width) as usize * 4..][..tile_width as usize * 4];
+                    stitched[start..start + tile_row.len()].copy_from_slice(tile_row);
+                }
+            }
+        }
+        assert!(
+            stitched == whole.data,
+            "the tiles differ from the whole image"
+        );
+    }
+}
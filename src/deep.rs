@@ -0,0 +1,587 @@
+//! Deep zoom renderer, for regions that are too small for `f64`.
+//!
+//! A single reference point is iterated with arbitrary precision, and every
+//! pixel only tracks its difference `delta` from the reference orbit in `f64`.
+//! This is the perturbation technique used for deep zooms into the Mandelbrot
+//! set, adapted to Newton's method.
+//!
+//! With `Z` being the reference point and `c_k = p^(k)(Z) / k!` the Taylor
+//! coefficients of the polynomial `p` around it, the Taylor coefficients
+//! around the pixel `z = Z + delta` differ from them by
+//!
+//! ```text
+//! a_k - c_k = delta * sum(binomial(j, k) * c_j * delta^(j-k-1), j > k)
+//! ```
+//!
+//! The step of the root-finding method is a rational function of these
+//! coefficients. It is evaluated on [`Perturbed`] numbers, which carry the
+//! reference value and the difference from it separately, so the difference
+//! of the steps comes out just as precise for tiny `delta` as it is for large
+//! ones. With relaxation, both steps are multiplied by the same factor, and so
+//! is their difference.
+//!
+//! Nova fractals add a constant `c` to each step. In the Mandelbrot variant,
+//! the constant varies by pixel instead of the starting point, so its
+//! difference from the constant of the reference orbit is added to `delta` in
+//! each iteration.
+
+use std::{
+    convert::TryFrom,
+    ops::{Add, Div, Mul, Neg, Sub},
+};
+
+use dashu_float::{round::mode::HalfAway, DBig, FBig};
+use glam::{DVec2, Vec2};
+use num_complex::Complex64;
+
+use crate::{
+    attractor,
+    config::{Basins, Config, Method, Nova, Region, MAX_HOUSEHOLDER_ORDER},
+    cpu::{self, Orbit, Params, Scalar},
+    image::Image,
+};
+
+/// Binary arbitrary-precision floating point number.
+type Real = FBig<HalfAway, 2>;
+
+/// Extra bits of precision for the reference orbit, beyond the size of a pixel.
+const GUARD_BITS: usize = 64;
+
+/// Upper limit for the precision needed to resolve a pixel, which is more
+/// than any image with a finite `f64` zoom needs.
+const MAX_PIXEL_BITS: f64 = 4096.0;
+
+/// Whether `region` is too small for [`cpu::render_region`] on an image of
+/// size `image_size`, so that it needs to be rendered with [`render_region`].
+pub fn is_needed(region: &Region, image_size: Vec2) -> bool {
+    !region.is_resolved_by(f64::EPSILON, image_size)
+}
+
+/// Renders `config` into a new image of the given size.
+pub fn render(config: &Config, width: u32, height: u32) -> Image {
+    let region = config.camera.region(Vec2::new(width as f32, height as f32));
+    let offset_region = Region {
+        center: DVec2::ZERO,
+        ..region
+    };
+    render_region(config, &offset_region, width, height)
+}
+
+/// Renders the given region into a new image.
+///
+/// The center of `offset_region` is relative to the camera position in
+/// `config`, which can be more precise than `f64`.
+pub fn render_region(config: &Config, offset_region: &Region, width: u32, height: u32) -> Image {
+    let mut params = Params::new(config, offset_region);
+    if config.basins == Basins::Attractors {
+        // The attractors are far larger than the pixels, so sampling them in
+        // double precision is good enough.
+        let samples = cpu::attractor_samples(config);
+        params.set_attractors(config, &attractor::find(config, &samples));
+    }
+    let reference = config.camera.position.translated(offset_region.center);
+    // Enough bits to resolve a pixel at the magnitude of the reference point.
+    let pixel_size = offset_region
+        .pixel_size(Vec2::new(width as f32, height as f32))
+        .min_element();
+    let magnitude = reference.to_dvec2().abs().max_element().max(1.0);
+    let pixel_bits = (magnitude / pixel_size).log2().ceil();
+    let precision = pixel_bits.clamp(0.0, MAX_PIXEL_BITS) as usize + GUARD_BITS;
+
+    let reference = BigComplex::from_decimal(&reference.re, &reference.im, precision);
+    let (start, c) = match config.nova {
+        Nova::Off => (reference, BigComplex::zero(precision)),
+        Nova::Mandelbrot => (
+            BigComplex::from_f64(params.critical_point(), precision),
+            reference,
+        ),
+        Nova::Julia => (
+            reference,
+            BigComplex::from_f64(
+                Complex64::new(config.nova_c.x as f64, config.nova_c.y as f64),
+                precision,
+            ),
+        ),
+    };
+    let orbit = ReferenceOrbit::new(config, &params, start, c, precision);
+    cpu::render_pixels(width, height, |clip| {
+        let delta = clip * offset_region.half_size;
+        let delta = Complex64::new(delta.x, delta.y);
+        let zero = Complex64::new(0.0, 0.0);
+        let (delta, delta_c) = match config.nova {
+            Nova::Mandelbrot => (zero, delta),
+            Nova::Off | Nova::Julia => (delta, zero),
+        };
+        params.color(&mut PerturbedOrbit {
+            params: &params,
+            orbit: &orbit,
+            iteration: 0,
+            delta,
+            delta_c,
+            direct: None,
+        })
+    })
+}
+
+/// The orbit of the reference point, with everything needed to iterate the
+/// pixels around it.
+struct ReferenceOrbit {
+    method: Method,
+    /// The number of Taylor coefficients that `method` needs, minus one.
+    order: usize,
+    relaxation: Complex64,
+    /// The constant that is added in each iteration.
+    c: Complex64,
+    /// Binomial coefficients `binomials[j][k]`, up to the degree of the
+    /// polynomial.
+    binomials: Vec<Vec<f64>>,
+    points: Vec<ReferencePoint>,
+    /// The point after the last iteration.
+    end: Complex64,
+    /// Whether the orbit has reached a fixed point, in which case the last
+    /// point repeats forever. Otherwise, the iteration could not continue.
+    converged: bool,
+}
+
+struct ReferencePoint {
+    position: Complex64,
+    /// Taylor coefficients of the polynomial around `position`, see the
+    /// module documentation.
+    taylor: Vec<Complex64>,
+    /// The step of the method from `position`, before it is scaled.
+    method_step: Complex64,
+    /// The factor from [`Params::multiplicity_factor`] at `position`.
+    multiplicity: f64,
+    /// The step from `position`, including the relaxation and the constant.
+    step: Complex64,
+}
+
+impl ReferenceOrbit {
+    fn new(
+        config: &Config,
+        params: &Params,
+        start: BigComplex,
+        c: BigComplex,
+        precision: usize,
+    ) -> Self {
+        let roots = config.root_factors();
+        let big_roots: Vec<_> = roots
+            .iter()
+            .map(|&root| BigComplex::from_f64(root, precision))
+            .collect();
+        let relaxation = Complex64::new(config.relaxation.x as f64, config.relaxation.y as f64);
+        let big_relaxation = BigComplex::from_f64(relaxation, precision);
+        let order = config.method.order(config.householder_order) as usize;
+
+        let mut points = Vec::new();
+        let mut position = start;
+        let mut converged = false;
+        while points.len() < config.num_iterations as usize {
+            let mut big_taylor = vec![BigComplex::zero(precision); order + 1];
+            factored_taylor(&big_roots, &position, &mut big_taylor);
+
+            // The higher coefficients only scale `delta`, so `f64` is precise
+            // enough for them.
+            let position_f64 = position.to_complex64();
+            let mut taylor = vec![Complex64::new(0.0, 0.0); roots.len() + 1];
+            factored_taylor(&roots, &position_f64, &mut taylor);
+            for (c, big_c) in taylor.iter_mut().zip(&big_taylor) {
+                *c = big_c.to_complex64();
+            }
+
+            let method_step = match cpu::method_step(config.method, &mut big_taylor) {
+                Some(step) => step,
+                None => break,
+            };
+            let multiplicity = params.multiplicity_factor(position_f64);
+            let big_multiplicity =
+                BigComplex::from_f64(Complex64::new(multiplicity, 0.0), precision);
+            let big_step = &(&big_relaxation * &(&big_multiplicity * &method_step)) - &c;
+            let next = &position - &big_step;
+            points.push(ReferencePoint {
+                position: position_f64,
+                taylor,
+                method_step: method_step.to_complex64(),
+                multiplicity,
+                step: big_step.to_complex64(),
+            });
+
+            converged = next == position;
+            position = next;
+            if converged {
+                break;
+            }
+        }
+        Self {
+            method: config.method,
+            order,
+            relaxation,
+            c: c.to_complex64(),
+            binomials: binomials(roots.len() + 1),
+            points,
+            end: position.to_complex64(),
+            converged,
+        }
+    }
+
+    fn get(&self, iteration: usize) -> Option<&ReferencePoint> {
+        match self.points.get(iteration) {
+            Some(point) => Some(point),
+            None if self.converged => self.points.last(),
+            None => None,
+        }
+    }
+
+    /// The reference position after `iteration` iterations.
+    fn position(&self, iteration: usize) -> Complex64 {
+        match self.points.get(iteration) {
+            Some(point) => point.position,
+            None => self.end,
+        }
+    }
+
+    /// The difference between the step at `reference.position + delta` and
+    /// the step at `reference.position`, without relaxation.
+    fn step_correction(&self, reference: &ReferencePoint, delta: Complex64) -> Complex64 {
+        let zero = Perturbed::from(Complex64::new(0.0, 0.0));
+        let mut taylor = [zero; MAX_HOUSEHOLDER_ORDER as usize + 1];
+        let taylor = &mut taylor[..=self.order];
+        for (k, a) in taylor.iter_mut().enumerate() {
+            let c = match reference.taylor.get(k) {
+                Some(&c) => c,
+                None => continue,
+            };
+            // Horner's method for the difference, see the module
+            // documentation.
+            let mut difference = Complex64::new(0.0, 0.0);
+            for j in (k + 1..reference.taylor.len()).rev() {
+                difference = difference * delta + self.binomials[j][k] * reference.taylor[j];
+            }
+            *a = Perturbed {
+                value: c,
+                difference: delta * difference,
+            };
+        }
+        match cpu::method_step(self.method, taylor) {
+            Some(step) => step.difference,
+            None => Complex64::new(f64::NAN, f64::NAN),
+        }
+    }
+}
+
+/// The orbit of a pixel, relative to the reference orbit.
+struct PerturbedOrbit<'a> {
+    params: &'a Params,
+    orbit: &'a ReferenceOrbit,
+    iteration: usize,
+    delta: Complex64,
+    /// The difference of the constant from the one of the reference orbit.
+    delta_c: Complex64,
+    /// Once the reference orbit ends without converging, or the pixel moves
+    /// away from it, the pixel is iterated directly from here on.
+    direct: Option<cpu::Direct<'a>>,
+}
+
+impl Orbit for PerturbedOrbit<'_> {
+    fn step(&mut self) -> Complex64 {
+        if self.direct.is_none() {
+            if let Some(reference) = self.orbit.get(self.iteration) {
+                // The multiplicity factor can change between the reference
+                // and the pixel if they are closest to different roots.
+                let multiplicity = self.params.multiplicity_factor(self.position());
+                let correction = self.orbit.relaxation
+                    * (multiplicity * self.orbit.step_correction(reference, self.delta)
+                        + (multiplicity - reference.multiplicity) * reference.method_step)
+                    - self.delta_c;
+                self.delta -= correction;
+                self.iteration += 1;
+                // Once the pixel is closer to zero than to the reference
+                // point, `delta` is no more precise than the position itself,
+                // and adding it to the reference loses digits.
+                let position = self.position();
+                if position.norm() < self.delta.norm() {
+                    self.direct = Some(cpu::Direct {
+                        params: self.params,
+                        position,
+                        c: self.orbit.c + self.delta_c,
+                    });
+                }
+                return reference.step + correction;
+            }
+            self.direct = Some(cpu::Direct {
+                params: self.params,
+                position: self.position(),
+                c: self.orbit.c + self.delta_c,
+            });
+        }
+        self.direct.as_mut().unwrap().step()
+    }
+
+    fn position(&self) -> Complex64 {
+        match &self.direct {
+            Some(direct) => direct.position(),
+            None => self.orbit.position(self.iteration) + self.delta,
+        }
+    }
+}
+
+/// Pascal's triangle with `n` rows.
+fn binomials(n: usize) -> Vec<Vec<f64>> {
+    let mut rows: Vec<Vec<f64>> = Vec::with_capacity(n);
+    for j in 0..n {
+        let mut row = vec![1.0; j + 1];
+        for k in 1..j {
+            row[k] = rows[j - 1][k - 1] + rows[j - 1][k];
+        }
+        rows.push(row);
+    }
+    rows
+}
+
+/// A number close to a reference `value`, as the value and the `difference`
+/// from it.
+///
+/// The arithmetic keeps track of the difference between the results for the
+/// number and for the reference value directly, so it does not get lost in the
+/// rounding of the results.
+#[derive(Clone, Copy)]
+struct Perturbed {
+    value: Complex64,
+    difference: Complex64,
+}
+
+impl From<Complex64> for Perturbed {
+    fn from(value: Complex64) -> Self {
+        Self {
+            value,
+            difference: Complex64::new(0.0, 0.0),
+        }
+    }
+}
+
+impl Scalar for Perturbed {
+    fn zero_like(&self) -> Self {
+        Complex64::new(0.0, 0.0).into()
+    }
+
+    fn one_like(&self) -> Self {
+        Complex64::new(1.0, 0.0).into()
+    }
+
+    fn is_zero(&self) -> bool {
+        (self.value + self.difference).is_zero()
+    }
+}
+
+impl Add for Perturbed {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            value: self.value + other.value,
+            difference: self.difference + other.difference,
+        }
+    }
+}
+
+impl Sub for Perturbed {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        Self {
+            value: self.value - other.value,
+            difference: self.difference - other.difference,
+        }
+    }
+}
+
+impl Mul for Perturbed {
+    type Output = Self;
+
+    fn mul(self, other: Self) -> Self {
+        Self {
+            value: self.value * other.value,
+            difference: self.difference * other.value
+                + self.value * other.difference
+                + self.difference * other.difference,
+        }
+    }
+}
+
+impl Div for Perturbed {
+    type Output = Self;
+
+    fn div(self, other: Self) -> Self {
+        Self {
+            value: self.value / other.value,
+            difference: (self.difference * other.value - self.value * other.difference)
+                / (other.value * (other.value + other.difference)),
+        }
+    }
+}
+
+impl Neg for Perturbed {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Self {
+            value: -self.value,
+            difference: -self.difference,
+        }
+    }
+}
+
+/// Fills `taylor` with the Taylor coefficients around `z` of the polynomial
+/// with the given `roots`, up to the length of `taylor`.
+///
+/// The factors `(z - root) + delta` are multiplied out as series in `delta`.
+/// Unlike evaluating the expanded polynomial, this stays precise close to
+/// multiple roots, where the low coefficients all approach zero.
+fn factored_taylor<T: Scalar>(roots: &[T], z: &T, taylor: &mut [T]) {
+    for a in taylor.iter_mut() {
+        *a = z.zero_like();
+    }
+    taylor[0] = z.one_like();
+    for root in roots {
+        let offset = z.clone() - root.clone();
+        for k in (1..taylor.len()).rev() {
+            taylor[k] = taylor[k].clone() * offset.clone() + taylor[k - 1].clone();
+        }
+        taylor[0] = taylor[0].clone() * offset;
+    }
+}
+
+/// Arbitrary-precision complex number.
+#[derive(Clone, PartialEq)]
+struct BigComplex {
+    re: Real,
+    im: Real,
+}
+
+impl BigComplex {
+    fn zero(precision: usize) -> Self {
+        Self::from_f64(Complex64::new(0.0, 0.0), precision)
+    }
+
+    fn from_f64(z: Complex64, precision: usize) -> Self {
+        let real = |x: f64| {
+            Real::try_from(x)
+                .expect("polynomial parameters are finite")
+                .with_precision(precision)
+                .value()
+        };
+        Self {
+            re: real(z.re),
+            im: real(z.im),
+        }
+    }
+
+    fn from_decimal(re: &DBig, im: &DBig, precision: usize) -> Self {
+        let real = |x: &DBig| x.clone().with_base_and_precision::<2>(precision).value();
+        Self {
+            re: real(re),
+            im: real(im),
+        }
+    }
+
+    fn to_complex64(&self) -> Complex64 {
+        Complex64::new(self.re.to_f64().value(), self.im.to_f64().value())
+    }
+}
+
+impl Scalar for BigComplex {
+    fn zero_like(&self) -> Self {
+        Self::zero(self.re.precision())
+    }
+
+    fn one_like(&self) -> Self {
+        Self::from_f64(Complex64::new(1.0, 0.0), self.re.precision())
+    }
+
+    fn is_zero(&self) -> bool {
+        self.re.repr().is_zero() && self.im.repr().is_zero()
+    }
+}
+
+impl Add for &BigComplex {
+    type Output = BigComplex;
+
+    fn add(self, other: Self) -> BigComplex {
+        BigComplex {
+            re: &self.re + &other.re,
+            im: &self.im + &other.im,
+        }
+    }
+}
+
+impl Sub for &BigComplex {
+    type Output = BigComplex;
+
+    fn sub(self, other: Self) -> BigComplex {
+        BigComplex {
+            re: &self.re - &other.re,
+            im: &self.im - &other.im,
+        }
+    }
+}
+
+impl Mul for &BigComplex {
+    type Output = BigComplex;
+
+    fn mul(self, other: Self) -> BigComplex {
+        BigComplex {
+            re: &self.re * &other.re - &self.im * &other.im,
+            im: &self.re * &other.im + &self.im * &other.re,
+        }
+    }
+}
+
+impl Div for &BigComplex {
+    type Output = BigComplex;
+
+    /// Divides by `other`, which must not be zero.
+    fn div(self, other: Self) -> BigComplex {
+        let norm = &other.re * &other.re + &other.im * &other.im;
+        BigComplex {
+            re: (&self.re * &other.re + &self.im * &other.im) / &norm,
+            im: (&self.im * &other.re - &self.re * &other.im) / &norm,
+        }
+    }
+}
+
+impl Neg for &BigComplex {
+    type Output = BigComplex;
+
+    fn neg(self) -> BigComplex {
+        BigComplex {
+            re: -&self.re,
+            im: -&self.im,
+        }
+    }
+}
+
+/// Implements the operators for owned values, in terms of the ones for
+/// references.
+macro_rules! forward_owned {
+    ($($trait:ident $method:ident),*) => {
+        $(
+            impl $trait for BigComplex {
+                type Output = BigComplex;
+
+                fn $method(self, other: Self) -> BigComplex {
+                    (&self).$method(&other)
+                }
+            }
+        )*
+    };
+}
+
+forward_owned!(Add add, Sub sub, Mul mul, Div div);
+
+impl Neg for BigComplex {
+    type Output = BigComplex;
+
+    fn neg(self) -> BigComplex {
+        -&self
+    }
+}
@@ -0,0 +1,262 @@
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::Path,
+    process::{Child, Command, Stdio},
+};
+
+use anyhow::{ensure, Context};
+use glam::{DVec2, Vec2};
+
+use crate::{
+    animation::Timeline,
+    config::{Config, Region},
+    cpu, deep,
+    fractal::FractalRenderer,
+};
+
+/// Upper bound on the size of the pixel data that is held in memory at once.
+const MAX_BAND_BYTES: usize = 64 << 20;
+
+/// Renders `config` at the given size and writes the result to a PNG file.
+///
+/// Uses the GPU renderer if one is available, otherwise falls back to the CPU
+/// renderer. Regions that are too small for `f64` are always rendered on the
+/// CPU, with the deep zoom renderer, which only supports polynomials.
+///
+/// The image is rendered in horizontal bands of tiles, where each tile is
+/// small enough to fit in a single texture. Each band is written to the file
+/// as soon as it is complete, so the memory use is bounded regardless of the
+/// size of the image.
+pub fn export_png(
+    renderer: Option<&mut FractalRenderer>,
+    config: &Config,
+    width: u32,
+    height: u32,
+    path: &Path,
+) -> anyhow::Result<()> {
+    let bands = Bands::new(config, width, height)?;
+    let mut png = create_png(path, width, height)?;
+    bands.render(renderer, |band| Ok(png.write_all(band)?))?;
+    png.finish()?;
+
+    log::info!("exported {}x{} image to {}", width, height, path.display());
+    Ok(())
+}
+
+/// Renders `timeline` at `fps` frames per second, from the first keyframe to
+/// the last one.
+///
+/// Each frame is written to `frames_dir` as `frame_00000.png` and so on,
+/// and/or piped into `encoder` as raw RGBA pixels. The encoder is a shell
+/// command that reads the frames from its standard input, e.g. `ffmpeg`.
+pub fn export_animation(
+    mut renderer: Option<&mut FractalRenderer>,
+    timeline: &Timeline,
+    fps: f64,
+    width: u32,
+    height: u32,
+    frames_dir: Option<&Path>,
+    encoder: Option<&str>,
+) -> anyhow::Result<()> {
+    ensure!(
+        fps.is_finite() && fps > 0.0,
+        "frame rate {} is not positive",
+        fps
+    );
+    ensure!(
+        !timeline.keyframes.is_empty(),
+        "the animation has no keyframes"
+    );
+    if let Some(dir) = frames_dir {
+        fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+    }
+    let mut encoder = encoder.map(spawn_encoder).transpose()?;
+
+    // The small offset keeps the last keyframe from being dropped when
+    // `duration * fps` is rounded down to just below a whole number.
+    let num_frames = (timeline.duration() * fps + 1e-6).floor() as u64 + 1;
+    let mut render_frames = || -> anyhow::Result<()> {
+        for frame in 0..num_frames {
+            let config = timeline
+                .sample(frame as f64 / fps)
+                .context("the animation has no keyframes")?;
+            let bands = Bands::new(&config, width, height)
+                .with_context(|| format!("failed to render frame {}", frame))?;
+            let mut png = frames_dir
+                .map(|dir| create_png(&dir.join(format!("frame_{:05}.png", frame)), width, height))
+                .transpose()?;
+            let mut stdin = encoder.as_mut().and_then(|child| child.stdin.as_mut());
+            bands.render(renderer.as_deref_mut(), |band| {
+                if let Some(png) = &mut png {
+                    png.write_all(band)?;
+                }
+                if let Some(stdin) = &mut stdin {
+                    stdin
+                        .write_all(band)
+                        .context("failed to write to the encoder")?;
+                }
+                Ok(())
+            })?;
+            if let Some(png) = png {
+                png.finish()?;
+            }
+            log::info!("rendered frame {}/{}", frame + 1, num_frames);
+        }
+        Ok(())
+    };
+    let result = render_frames();
+
+    if let Some(mut child) = encoder {
+        // Closing stdin tells the encoder that there are no more frames. Its
+        // exit status explains a failure to write to it better than the write
+        // error does, so it is checked first.
+        drop(child.stdin.take());
+        let status = child.wait()?;
+        ensure!(status.success(), "encoder failed with {}", status);
+    }
+
+    result?;
+    log::info!(
+        "exported {} frames of {}x{} at {} fps",
+        num_frames,
+        width,
+        height,
+        fps
+    );
+    Ok(())
+}
+
+/// An image that is rendered in horizontal bands of tiles.
+struct Bands<'a> {
+    config: &'a Config,
+    region: Region,
+    /// Whether the image is rendered with the deep zoom renderer.
+    deep: bool,
+    width: u32,
+    height: u32,
+}
+
+impl<'a> Bands<'a> {
+    fn new(config: &'a Config, width: u32, height: u32) -> anyhow::Result<Self> {
+        ensure!(
+            width > 0 && height > 0,
+            "image size {}x{} is empty",
+            width,
+            height
+        );
+        let image_size = Vec2::new(width as f32, height as f32);
+        let region = config.camera.region(image_size);
+        let deep = deep::is_needed(&region, image_size);
+        ensure!(
+            !deep || config.function.is_none(),
+            "functions can't be rendered this deep, only polynomials"
+        );
+        // The deep zoom renderer takes regions relative to the camera position,
+        // which can't be rounded to `f64`.
+        let region = if deep {
+            Region {
+                center: DVec2::ZERO,
+                ..region
+            }
+        } else {
+            region
+        };
+        Ok(Self {
+            config,
+            region,
+            deep,
+            width,
+            height,
+        })
+    }
+
+    /// Renders the image and passes the opaque RGBA pixels of each band to
+    /// `write`, from top to bottom.
+    fn render(
+        &self,
+        mut renderer: Option<&mut FractalRenderer>,
+        mut write: impl FnMut(&[u8]) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        let Self {
+            config,
+            ref region,
+            deep,
+            width,
+            height,
+        } = *self;
+        let max_tile_size = match &renderer {
+            Some(renderer) if !deep => renderer.max_image_size(),
+            _ => u32::MAX,
+        };
+        let row_bytes = width as usize * 4;
+        let tile_width = width.min(max_tile_size);
+        let tile_height = ((MAX_BAND_BYTES / row_bytes).max(1) as u32)
+            .min(height)
+            .min(max_tile_size);
+
+        let mut band = Vec::new();
+        for y in (0..height).step_by(tile_height as usize) {
+            let band_height = tile_height.min(height - y);
+            band.clear();
+            band.resize(row_bytes * band_height as usize, 0);
+
+            for x in (0..width).step_by(tile_width as usize) {
+                let tile_width = tile_width.min(width - x);
+                let tile_region = region.tile(width, height, x, y, tile_width, band_height);
+                let tile = match renderer.as_deref_mut() {
+                    _ if deep => deep::render_region(config, &tile_region, tile_width, band_height),
+                    Some(renderer) => {
+                        renderer.render_image(config, &tile_region, tile_width, band_height)?
+                    }
+                    None => cpu::render_region(config, &tile_region, tile_width, band_height),
+                };
+
+                let offset = x as usize * 4;
+                let tile_row_bytes = tile_width as usize * 4;
+                for (band_row, tile_row) in band
+                    .chunks_exact_mut(row_bytes)
+                    .zip(tile.data.chunks_exact(tile_row_bytes))
+                {
+                    band_row[offset..offset + tile_row_bytes].copy_from_slice(tile_row);
+                }
+            }
+            // The window ignores the alpha of the colors, which is zero for
+            // some of the default ones, so the image is made opaque to match.
+            for pixel in band.chunks_exact_mut(4) {
+                pixel[3] = u8::MAX;
+            }
+
+            write(&band)?;
+        }
+        Ok(())
+    }
+}
+
+/// Creates a PNG file at `path` that RGBA pixels can be streamed into.
+fn create_png(
+    path: &Path,
+    width: u32,
+    height: u32,
+) -> anyhow::Result<png::StreamWriter<'static, BufWriter<File>>> {
+    let file =
+        File::create(path).with_context(|| format!("failed to create {}", path.display()))?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    Ok(encoder.write_header()?.into_stream_writer()?)
+}
+
+/// Starts the shell command `command` with a pipe to its standard input.
+fn spawn_encoder(command: &str) -> anyhow::Result<Child> {
+    let (shell, flag) = if cfg!(windows) {
+        ("cmd", "/C")
+    } else {
+        ("sh", "-c")
+    };
+    Command::new(shell)
+        .args([flag, command])
+        .stdin(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("failed to start encoder {:?}", command))
+}
@@ -0,0 +1,767 @@
+//! Functions given as expressions, like `sin(z) - z / 2`, which are iterated
+//! instead of the polynomial.
+//!
+//! An expression is parsed into a graph of nodes, where equal subexpressions
+//! are shared. The derivatives that the root-finding methods need are built
+//! symbolically in the same graph, so repeated differentiation of products and
+//! quotients only grows it gradually. The result is evaluated directly on the
+//! CPU, and compiled to WGSL for the shader.
+
+use std::{collections::HashMap, fmt};
+
+use num_complex::Complex64;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::lexer::Lexer;
+
+/// The length of the Taylor coefficient arrays in `fractal.wgsl`, i.e.
+/// `config::MAX_HOUSEHOLDER_ORDER + 1`.
+const WGSL_TAYLOR_LENGTH: usize = 17;
+
+/// Integer exponents up to this magnitude are kept as powers, larger ones
+/// are computed like any other exponent. Each derivative lowers a power by one,
+/// so this keeps the exponents of the derivatives far from overflowing.
+const MAX_INTEGER_EXPONENT: f64 = 1024.0;
+
+/// How many starting points [`Function::find_roots`] uses along each axis.
+const ROOT_GRID_SIZE: usize = 48;
+
+/// Iteration limit of [`Function::find_roots`] for a single starting point.
+const MAX_ROOT_ITERATIONS: usize = 100;
+
+/// Relative step size below which [`Function::find_roots`] considers a point
+/// to be a root.
+const ROOT_TOLERANCE: f64 = 1e-12;
+
+/// Roots found by [`Function::find_roots`] that are closer than this,
+/// relative to their magnitude, are merged.
+const ROOT_MERGE_DISTANCE: f64 = 1e-6;
+
+/// The most roots that [`Function::find_roots`] returns.
+const MAX_ROOTS: usize = 32;
+
+/// A function of `z`, parsed from an expression.
+///
+/// Serialized as the expression it was parsed from.
+#[derive(Clone)]
+pub struct Function {
+    source: String,
+    graph: Graph,
+    value: Id,
+}
+
+impl Function {
+    /// Parses an expression in `z`, optionally prefixed with `f(z) =`.
+    ///
+    /// Besides the arithmetic operators and `^`, expressions can contain the
+    /// constants `i`, `pi` and `e`, and the functions `sin`, `cos`, `tan`,
+    /// `sinh`, `cosh`, `tanh`, `exp`, `log` (or `ln`) and `sqrt`.
+    /// Multiplication can be implicit, like in `2z sin(z)`.
+    pub fn parse(source: &str) -> anyhow::Result<Self> {
+        let mut parser = Parser {
+            lexer: Lexer::new(source),
+            graph: Graph::default(),
+        };
+        parser.prefix();
+        let value = parser.expression()?;
+        parser.lexer.finish()?;
+        Ok(Self {
+            source: source.trim().to_owned(),
+            graph: parser.graph,
+            value,
+        })
+    }
+
+    /// The expression that this function was parsed from.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Builds the first `order` derivatives of the function.
+    pub fn taylor(&self, order: usize) -> Taylor {
+        let mut graph = self.graph.clone();
+        let mut outputs = vec![self.value];
+        let mut derivative = self.value;
+        let mut factorial = 1.0;
+        for k in 1..=order {
+            derivative = graph.derivative(derivative, &mut HashMap::new());
+            factorial *= k as f64;
+            let scale = graph.constant(Complex64::new(factorial.recip(), 0.0));
+            outputs.push(graph.mul(scale, derivative));
+        }
+        let live = graph.live(&outputs);
+        Taylor {
+            graph,
+            outputs,
+            live,
+        }
+    }
+
+    /// Finds the roots of the function in the square with half-width `radius`
+    /// around `center`.
+    ///
+    /// Newton's method is run from a grid of starting points, and the points
+    /// that it converges to are merged. The roots are sorted by how many
+    /// starting points reached them, and only the first [`MAX_ROOTS`] are
+    /// kept.
+    pub fn find_roots(&self, center: Complex64, radius: f64) -> Vec<Complex64> {
+        let taylor = self.taylor(1);
+        let mut roots: Vec<(Complex64, usize)> = Vec::new();
+        let mut values = [Complex64::new(0.0, 0.0); 2];
+        for i in 0..ROOT_GRID_SIZE {
+            for j in 0..ROOT_GRID_SIZE {
+                let grid = |k: usize| 2.0 * (k as f64 + 0.5) / ROOT_GRID_SIZE as f64 - 1.0;
+                let mut z = center + radius * Complex64::new(grid(i), grid(j));
+                for _ in 0..MAX_ROOT_ITERATIONS {
+                    taylor.evaluate(z, &mut values);
+                    let step = values[0] / values[1];
+                    z -= step;
+                    if !z.is_finite() {
+                        break;
+                    }
+                    if step.norm() > ROOT_TOLERANCE * z.norm().max(1.0) {
+                        continue;
+                    }
+                    let merge_distance = ROOT_MERGE_DISTANCE * z.norm().max(1.0);
+                    match roots
+                        .iter_mut()
+                        .find(|(root, _)| (*root - z).norm() < merge_distance)
+                    {
+                        Some((_, count)) => *count += 1,
+                        None => roots.push((z, 1)),
+                    }
+                    break;
+                }
+            }
+        }
+        roots.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+        roots.truncate(MAX_ROOTS);
+        roots.into_iter().map(|(root, _)| root).collect()
+    }
+}
+
+impl fmt::Debug for Function {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Function").field(&self.source).finish()
+    }
+}
+
+impl Serialize for Function {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.source)
+    }
+}
+
+impl<'de> Deserialize<'de> for Function {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let source = String::deserialize(deserializer)?;
+        Self::parse(&source).map_err(de::Error::custom)
+    }
+}
+
+/// The normalized Taylor coefficients `f^(k)(z) / k!` of a [`Function`], up to
+/// some order.
+pub struct Taylor {
+    graph: Graph,
+    /// The node of each Taylor coefficient.
+    outputs: Vec<Id>,
+    /// The nodes that the outputs depend on, in ascending order.
+    live: Vec<Id>,
+}
+
+impl Taylor {
+    /// Fills `taylor` with the Taylor coefficients around `z`, up to its
+    /// length or the order of this, whichever is lower.
+    pub fn evaluate(&self, z: Complex64, taylor: &mut [Complex64]) {
+        let mut values = vec![Complex64::new(0.0, 0.0); self.graph.nodes.len()];
+        // Operands are always inserted before the nodes that use them.
+        for &id in &self.live {
+            values[id] = match self.graph.nodes[id] {
+                Node::Z => z,
+                Node::Constant(c) => c.value(),
+                Node::Add(a, b) => values[a] + values[b],
+                Node::Sub(a, b) => values[a] - values[b],
+                Node::Mul(a, b) => values[a] * values[b],
+                Node::Div(a, b) => values[a] / values[b],
+                Node::Neg(a) => -values[a],
+                Node::Powi(a, n) => values[a].powi(n),
+                Node::Call(builtin, a) => builtin.evaluate(values[a]),
+            };
+        }
+        for (a, &id) in taylor.iter_mut().zip(&self.outputs) {
+            *a = values[id];
+        }
+    }
+
+    /// Compiles this to the WGSL that defines `FUNCTION_DEFINED` and
+    /// `function_taylor` for `fractal.wgsl`.
+    ///
+    /// The code uses the `Complex` type and the functions from
+    /// `complex_functions.wgsl`.
+    pub fn to_wgsl(&self) -> String {
+        let mut body = String::new();
+        for &id in &self.live {
+            let expression = match self.graph.nodes[id] {
+                Node::Z => "z".to_owned(),
+                Node::Constant(c) => {
+                    let c = c.value();
+                    let hi = (c.re as f32, c.im as f32);
+                    let lo = ((c.re - hi.0 as f64) as f32, (c.im - hi.1 as f64) as f32);
+                    format!(
+                        "c_from_split({}, {})",
+                        wgsl_vec2(hi.0, hi.1),
+                        wgsl_vec2(lo.0, lo.1)
+                    )
+                }
+                Node::Add(a, b) => format!("c_add(v{}, v{})", a, b),
+                Node::Sub(a, b) => format!("c_sub(v{}, v{})", a, b),
+                Node::Mul(a, b) => format!("c_mul(v{}, v{})", a, b),
+                Node::Div(a, b) => format!("c_div(v{}, v{})", a, b),
+                Node::Neg(a) => format!("-v{}", a),
+                Node::Powi(a, n) => format!("c_powi(v{}, {})", a, n),
+                Node::Call(builtin, a) => format!("c_{}(v{})", builtin.name(), a),
+            };
+            body.push_str(&format!("    let v{} = {};\n", id, expression));
+        }
+        for (k, id) in self.outputs.iter().enumerate().take(WGSL_TAYLOR_LENGTH) {
+            body.push_str(&format!("    taylor[{}] = v{};\n", k, id));
+        }
+        format!(
+            "let FUNCTION_DEFINED: bool = true;\n\
+             \n\
+             fn function_taylor(z: Complex) -> array<Complex, {length}> {{\n\
+             \x20   var taylor: array<Complex, {length}>;\n\
+             {body}\
+             \x20   return taylor;\n\
+             }}\n",
+            length = WGSL_TAYLOR_LENGTH,
+            body = body,
+        )
+    }
+}
+
+/// A `vec2<f32>` literal, which keeps the exact value even if it is not
+/// finite.
+fn wgsl_vec2(x: f32, y: f32) -> String {
+    if x.is_finite() && y.is_finite() {
+        format!("vec2<f32>({:?}, {:?})", x, y)
+    } else {
+        format!(
+            "bitcast<vec2<f32>>(vec2<u32>({}u, {}u))",
+            x.to_bits(),
+            y.to_bits()
+        )
+    }
+}
+
+/// Index of a node in a [`Graph`].
+type Id = usize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Node {
+    Z,
+    Constant(Constant),
+    Add(Id, Id),
+    Sub(Id, Id),
+    Mul(Id, Id),
+    Div(Id, Id),
+    Neg(Id),
+    /// Integer power.
+    Powi(Id, i32),
+    Call(Builtin, Id),
+}
+
+/// A complex constant, stored as the bits of its parts so that nodes can be
+/// hashed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Constant(u64, u64);
+
+impl Constant {
+    fn new(c: Complex64) -> Self {
+        Self(c.re.to_bits(), c.im.to_bits())
+    }
+
+    fn value(self) -> Complex64 {
+        Complex64::new(f64::from_bits(self.0), f64::from_bits(self.1))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Builtin {
+    Sin,
+    Cos,
+    Tan,
+    Sinh,
+    Cosh,
+    Tanh,
+    Exp,
+    Log,
+    Sqrt,
+}
+
+impl Builtin {
+    const ALL: [Self; 9] = [
+        Self::Sin,
+        Self::Cos,
+        Self::Tan,
+        Self::Sinh,
+        Self::Cosh,
+        Self::Tanh,
+        Self::Exp,
+        Self::Log,
+        Self::Sqrt,
+    ];
+
+    /// The name in expressions, which is also the name of the WGSL function
+    /// without the `c_` prefix.
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Sin => "sin",
+            Self::Cos => "cos",
+            Self::Tan => "tan",
+            Self::Sinh => "sinh",
+            Self::Cosh => "cosh",
+            Self::Tanh => "tanh",
+            Self::Exp => "exp",
+            Self::Log => "log",
+            Self::Sqrt => "sqrt",
+        }
+    }
+
+    fn evaluate(&self, z: Complex64) -> Complex64 {
+        match self {
+            Self::Sin => z.sin(),
+            Self::Cos => z.cos(),
+            Self::Tan => z.tan(),
+            Self::Sinh => z.sinh(),
+            Self::Cosh => z.cosh(),
+            Self::Tanh => z.tanh(),
+            Self::Exp => z.exp(),
+            Self::Log => z.ln(),
+            Self::Sqrt => z.sqrt(),
+        }
+    }
+}
+
+/// Expression graph where every distinct node is only stored once.
+///
+/// The constructors fold constants and drop operations that don't change the
+/// value, which keeps the derivatives small.
+#[derive(Debug, Clone, Default)]
+struct Graph {
+    nodes: Vec<Node>,
+    ids: HashMap<Node, Id>,
+}
+
+impl Graph {
+    fn insert(&mut self, node: Node) -> Id {
+        if let Some(&id) = self.ids.get(&node) {
+            return id;
+        }
+        let id = self.nodes.len();
+        self.nodes.push(node);
+        self.ids.insert(node, id);
+        id
+    }
+
+    fn z(&mut self) -> Id {
+        self.insert(Node::Z)
+    }
+
+    fn constant(&mut self, c: Complex64) -> Id {
+        self.insert(Node::Constant(Constant::new(c)))
+    }
+
+    fn value(&self, id: Id) -> Option<Complex64> {
+        match self.nodes[id] {
+            Node::Constant(c) => Some(c.value()),
+            _ => None,
+        }
+    }
+
+    fn is(&self, id: Id, c: f64) -> bool {
+        self.value(id) == Some(Complex64::new(c, 0.0))
+    }
+
+    fn add(&mut self, a: Id, b: Id) -> Id {
+        match (self.value(a), self.value(b)) {
+            (Some(x), Some(y)) => self.constant(x + y),
+            _ if self.is(a, 0.0) => b,
+            _ if self.is(b, 0.0) => a,
+            _ => self.insert(Node::Add(a, b)),
+        }
+    }
+
+    fn sub(&mut self, a: Id, b: Id) -> Id {
+        match (self.value(a), self.value(b)) {
+            (Some(x), Some(y)) => self.constant(x - y),
+            _ if self.is(b, 0.0) => a,
+            _ if self.is(a, 0.0) => self.neg(b),
+            _ if a == b => self.constant(Complex64::new(0.0, 0.0)),
+            _ => self.insert(Node::Sub(a, b)),
+        }
+    }
+
+    fn mul(&mut self, a: Id, b: Id) -> Id {
+        match (self.value(a), self.value(b)) {
+            (Some(x), Some(y)) => self.constant(x * y),
+            _ if self.is(a, 0.0) || self.is(b, 0.0) => self.constant(Complex64::new(0.0, 0.0)),
+            _ if self.is(a, 1.0) => b,
+            _ if self.is(b, 1.0) => a,
+            _ if self.is(a, -1.0) => self.neg(b),
+            _ if self.is(b, -1.0) => self.neg(a),
+            _ => self.insert(Node::Mul(a, b)),
+        }
+    }
+
+    fn div(&mut self, a: Id, b: Id) -> Id {
+        match (self.value(a), self.value(b)) {
+            (Some(x), Some(y)) => self.constant(x / y),
+            _ if self.is(a, 0.0) => a,
+            _ if self.is(b, 1.0) => a,
+            _ => self.insert(Node::Div(a, b)),
+        }
+    }
+
+    fn neg(&mut self, a: Id) -> Id {
+        match self.nodes[a] {
+            Node::Constant(c) => self.constant(-c.value()),
+            Node::Neg(b) => b,
+            _ => self.insert(Node::Neg(a)),
+        }
+    }
+
+    fn powi(&mut self, a: Id, n: i32) -> Id {
+        match (self.value(a), n) {
+            (_, 0) => self.constant(Complex64::new(1.0, 0.0)),
+            (_, 1) => a,
+            (Some(x), _) => self.constant(x.powi(n)),
+            _ => self.insert(Node::Powi(a, n)),
+        }
+    }
+
+    fn call(&mut self, builtin: Builtin, a: Id) -> Id {
+        match self.value(a) {
+            Some(x) => self.constant(builtin.evaluate(x)),
+            None => self.insert(Node::Call(builtin, a)),
+        }
+    }
+
+    /// The derivative of the node `id` with respect to `z`.
+    ///
+    /// `cache` holds the derivatives that were already built, so that shared
+    /// subexpressions are only differentiated once.
+    fn derivative(&mut self, id: Id, cache: &mut HashMap<Id, Id>) -> Id {
+        if let Some(&derivative) = cache.get(&id) {
+            return derivative;
+        }
+        let derivative = match self.nodes[id] {
+            Node::Z => self.constant(Complex64::new(1.0, 0.0)),
+            Node::Constant(_) => self.constant(Complex64::new(0.0, 0.0)),
+            Node::Add(a, b) => {
+                let (da, db) = (self.derivative(a, cache), self.derivative(b, cache));
+                self.add(da, db)
+            }
+            Node::Sub(a, b) => {
+                let (da, db) = (self.derivative(a, cache), self.derivative(b, cache));
+                self.sub(da, db)
+            }
+            Node::Mul(a, b) => {
+                let (da, db) = (self.derivative(a, cache), self.derivative(b, cache));
+                let (da_b, a_db) = (self.mul(da, b), self.mul(a, db));
+                self.add(da_b, a_db)
+            }
+            Node::Div(a, b) => {
+                // (a / b)' = (a' - (a / b) b') / b, which reuses the quotient.
+                let (da, db) = (self.derivative(a, cache), self.derivative(b, cache));
+                let quotient_db = self.mul(id, db);
+                let numerator = self.sub(da, quotient_db);
+                self.div(numerator, b)
+            }
+            Node::Neg(a) => {
+                let da = self.derivative(a, cache);
+                self.neg(da)
+            }
+            Node::Powi(a, n) => {
+                let da = self.derivative(a, cache);
+                let factor = self.constant(Complex64::new(n as f64, 0.0));
+                let power = self.powi(a, n - 1);
+                let outer = self.mul(factor, power);
+                self.mul(outer, da)
+            }
+            Node::Call(builtin, a) => {
+                let da = self.derivative(a, cache);
+                let outer = match builtin {
+                    Builtin::Sin => self.call(Builtin::Cos, a),
+                    Builtin::Cos => {
+                        let sin = self.call(Builtin::Sin, a);
+                        self.neg(sin)
+                    }
+                    Builtin::Tan => {
+                        let cos = self.call(Builtin::Cos, a);
+                        let one = self.constant(Complex64::new(1.0, 0.0));
+                        let cos_squared = self.powi(cos, 2);
+                        self.div(one, cos_squared)
+                    }
+                    Builtin::Sinh => self.call(Builtin::Cosh, a),
+                    Builtin::Cosh => self.call(Builtin::Sinh, a),
+                    Builtin::Tanh => {
+                        let cosh = self.call(Builtin::Cosh, a);
+                        let one = self.constant(Complex64::new(1.0, 0.0));
+                        let cosh_squared = self.powi(cosh, 2);
+                        self.div(one, cosh_squared)
+                    }
+                    Builtin::Exp => id,
+                    Builtin::Log => {
+                        let one = self.constant(Complex64::new(1.0, 0.0));
+                        self.div(one, a)
+                    }
+                    Builtin::Sqrt => {
+                        let half = self.constant(Complex64::new(0.5, 0.0));
+                        self.div(half, id)
+                    }
+                };
+                self.mul(outer, da)
+            }
+        };
+        cache.insert(id, derivative);
+        derivative
+    }
+
+    /// The nodes that `outputs` depend on, in ascending order.
+    fn live(&self, outputs: &[Id]) -> Vec<Id> {
+        let mut is_live = vec![false; self.nodes.len()];
+        for &id in outputs {
+            is_live[id] = true;
+        }
+        for id in (0..self.nodes.len()).rev() {
+            if !is_live[id] {
+                continue;
+            }
+            match self.nodes[id] {
+                Node::Z | Node::Constant(_) => {}
+                Node::Add(a, b) | Node::Sub(a, b) | Node::Mul(a, b) | Node::Div(a, b) => {
+                    is_live[a] = true;
+                    is_live[b] = true;
+                }
+                Node::Neg(a) | Node::Powi(a, _) | Node::Call(_, a) => is_live[a] = true,
+            }
+        }
+        (0..self.nodes.len()).filter(|&id| is_live[id]).collect()
+    }
+}
+
+/// Recursive descent parser for [`Function::parse`], which builds the graph as
+/// it goes.
+///
+/// Sums and products follow the grammar in [`crate::lexer`].
+struct Parser {
+    lexer: Lexer,
+    graph: Graph,
+}
+
+impl Parser {
+    /// Skips an optional `f(z) =` before the expression.
+    fn prefix(&mut self) {
+        let start = self.lexer.pos;
+        if !(self.lexer.eat('f')
+            && self.lexer.eat('(')
+            && self.lexer.eat('z')
+            && self.lexer.eat(')')
+            && self.lexer.eat('='))
+        {
+            self.lexer.pos = start;
+        }
+    }
+
+    fn expression(&mut self) -> anyhow::Result<Id> {
+        let negative = !self.lexer.eat('+') && self.lexer.eat('-');
+        let mut result = self.term()?;
+        if negative {
+            result = self.graph.neg(result);
+        }
+        loop {
+            if self.lexer.eat('+') {
+                let term = self.term()?;
+                result = self.graph.add(result, term);
+            } else if self.lexer.eat('-') {
+                let term = self.term()?;
+                result = self.graph.sub(result, term);
+            } else {
+                return Ok(result);
+            }
+        }
+    }
+
+    fn term(&mut self) -> anyhow::Result<Id> {
+        let mut result = self.factor()?;
+        loop {
+            if self.lexer.eat('/') {
+                let divisor = self.factor()?;
+                result = self.graph.div(result, divisor);
+            } else if self.lexer.eat('*') || self.starts_factor() {
+                let factor = self.factor()?;
+                result = self.graph.mul(result, factor);
+            } else {
+                return Ok(result);
+            }
+        }
+    }
+
+    fn starts_factor(&mut self) -> bool {
+        matches!(self.lexer.peek(), Some(c) if c.is_ascii_alphanumeric() || c == '.' || c == '(')
+    }
+
+    /// `factor = primary ["^" ["+" | "-"] factor]`
+    ///
+    /// Integer exponents up to [`MAX_INTEGER_EXPONENT`] are kept as powers,
+    /// anything else becomes `exp(exponent * log(base))`.
+    fn factor(&mut self) -> anyhow::Result<Id> {
+        let base = self.primary()?;
+        if !self.lexer.eat('^') {
+            return Ok(base);
+        }
+        let negative = !self.lexer.eat('+') && self.lexer.eat('-');
+        let mut exponent = self.factor()?;
+        if negative {
+            exponent = self.graph.neg(exponent);
+        }
+        match self.graph.value(exponent) {
+            Some(n) if n.im == 0.0 && n.re.fract() == 0.0 && n.re.abs() <= MAX_INTEGER_EXPONENT => {
+                Ok(self.graph.powi(base, n.re as i32))
+            }
+            _ => {
+                let log = self.graph.call(Builtin::Log, base);
+                let product = self.graph.mul(exponent, log);
+                Ok(self.graph.call(Builtin::Exp, product))
+            }
+        }
+    }
+
+    /// `primary = number | name | function "(" expression ")" | "(" expression ")"`
+    fn primary(&mut self) -> anyhow::Result<Id> {
+        match self.lexer.peek() {
+            Some('(') => {
+                self.lexer.pos += 1;
+                let result = self.expression()?;
+                anyhow::ensure!(
+                    self.lexer.eat(')'),
+                    "missing ')' at position {}",
+                    self.lexer.pos + 1
+                );
+                Ok(result)
+            }
+            Some(c) if c.is_ascii_digit() || c == '.' => {
+                let number = self.lexer.number()?;
+                Ok(self.graph.constant(Complex64::new(number, 0.0)))
+            }
+            Some(c) if c.is_ascii_alphabetic() => self.name(),
+            Some(c) => anyhow::bail!("unexpected {:?} at position {}", c, self.lexer.pos + 1),
+            None => anyhow::bail!("unexpected end of the expression"),
+        }
+    }
+
+    /// A variable, constant or function call. The longest known name wins, so
+    /// that `iz` is `i * z` and `sinh` is not `sin h`.
+    fn name(&mut self) -> anyhow::Result<Id> {
+        let start = self.lexer.pos;
+        let rest = self.lexer.letters();
+        let builtin = Builtin::ALL
+            .iter()
+            .copied()
+            .filter(|builtin| rest.starts_with(builtin.name()))
+            .max_by_key(|builtin| builtin.name().len());
+        let builtin = match builtin {
+            Some(builtin) => Some((builtin, builtin.name().len())),
+            None if rest.starts_with("ln") => Some((Builtin::Log, 2)),
+            None => None,
+        };
+        if let Some((builtin, length)) = builtin {
+            self.lexer.pos += length;
+            anyhow::ensure!(
+                self.lexer.eat('('),
+                "missing '(' after {} at position {}",
+                builtin.name(),
+                self.lexer.pos + 1
+            );
+            let argument = self.expression()?;
+            anyhow::ensure!(
+                self.lexer.eat(')'),
+                "missing ')' at position {}",
+                self.lexer.pos + 1
+            );
+            return Ok(self.graph.call(builtin, argument));
+        }
+        let (id, length) = if rest.starts_with("pi") {
+            let pi = Complex64::new(std::f64::consts::PI, 0.0);
+            (self.graph.constant(pi), 2)
+        } else if rest.starts_with('z') {
+            (self.graph.z(), 1)
+        } else if rest.starts_with('i') {
+            (self.graph.constant(Complex64::new(0.0, 1.0)), 1)
+        } else if rest.starts_with('e') {
+            let e = Complex64::new(std::f64::consts::E, 0.0);
+            (self.graph.constant(e), 1)
+        } else {
+            anyhow::bail!("unknown name {:?} at position {}", rest, start + 1);
+        };
+        self.lexer.pos += length;
+        Ok(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The Taylor coefficients of `source` around `z` up to `order`.
+    fn taylor(source: &str, z: Complex64, order: usize) -> Vec<Complex64> {
+        let mut taylor = vec![Complex64::new(0.0, 0.0); order + 1];
+        Function::parse(source)
+            .unwrap()
+            .taylor(order)
+            .evaluate(z, &mut taylor);
+        taylor
+    }
+
+    fn assert_close(a: &[Complex64], b: &[Complex64], tolerance: f64) {
+        assert_eq!(a.len(), b.len());
+        for (x, y) in a.iter().zip(b) {
+            assert!(
+                (x - y).norm() <= tolerance * y.norm().max(1.0),
+                "{:?} != {:?}",
+                a,
+                b
+            );
+        }
+    }
+
+    #[test]
+    fn taylor_matches_closed_forms() {
+        let z = Complex64::new(0.7, 0.3);
+        let (sin, cos) = (z.sin(), z.cos());
+        // The derivatives of sin(z) - z/2, divided by k!.
+        let expected = [sin - z / 2.0, cos - 0.5, -sin / 2.0, -cos / 6.0, sin / 24.0];
+        assert_close(&taylor("sin(z) - z/2", z, 4), &expected, 1e-12);
+        assert_close(&taylor("f(z) = sin(z) - z / 2", z, 4), &expected, 1e-12);
+    }
+
+    #[test]
+    fn integer_powers_are_exact() {
+        let z = Complex64::new(2.0, 0.0);
+        let expected = [8.0, 12.0, 6.0, 1.0, 0.0].map(|x| Complex64::new(x, 0.0));
+        assert_close(&taylor("z^3", z, 4), &expected, 0.0);
+    }
+
+    #[test]
+    fn large_exponents_dont_overflow() {
+        let z = Complex64::new(1.0, 0.0);
+        let n = -(i32::MAX as f64);
+        let taylor = taylor("z^-2147483647", z, 16);
+        assert_close(
+            &taylor[..3],
+            &[1.0, n, n * (n - 1.0) / 2.0].map(|x| Complex64::new(x, 0.0)),
+            1e-9,
+        );
+    }
+}
@@ -1,16 +1,138 @@
-use crate::{config::Config, GraphicsContext};
+use std::{
+    borrow::Cow,
+    future::Future,
+    num::NonZeroU32,
+    pin::Pin,
+    task::{self, Poll, Waker},
+};
+
+use crate::{
+    attractor::{self, Attractor, Sample},
+    config::{Basins, Config, Region, RootConfig},
+    image::Image,
+    GraphicsContext,
+};
+use anyhow::{ensure, Context};
 use bytemuck::{Pod, Zeroable};
-use glam::Vec2;
+use glam::{DVec2, Vec2};
+use num_complex::Complex64;
+use pollster::block_on;
 use wgpu::util::DeviceExt;
 
-const MAX_ROOTS: usize = 10;
-const MAX_COEFFICIENTS: usize = 1 + MAX_ROOTS;
+/// The arithmetic that the shader uses for points on the complex plane.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Precision {
+    Single,
+    /// Native `f64`, if the adapter supports `SHADER_FLOAT64`.
+    Double,
+    /// Double precision emulated with pairs of `f32`, with a mantissa of
+    /// about 48 bits.
+    DoubleSingle,
+}
+
+impl Precision {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Single => "Single",
+            Self::Double => "Double",
+            Self::DoubleSingle => "Double (emulated)",
+        }
+    }
+
+    /// The file defining the `Complex` type and its arithmetic for this
+    /// precision, which is prepended to the other shader files.
+    fn shader_prelude(&self) -> &'static str {
+        match self {
+            Self::Single => include_str!("complex_f32.wgsl"),
+            Self::Double => include_str!("complex_f64.wgsl"),
+            Self::DoubleSingle => include_str!("complex_ds.wgsl"),
+        }
+    }
+}
 
 pub struct FractalRenderer {
     gfx: GraphicsContext,
     params_buffer: wgpu::Buffer,
-    render_pipeline: wgpu::RenderPipeline,
+    roots_buffer: StorageBuffer,
+    coefficients_buffer: StorageBuffer,
+    attractors_buffer: StorageBuffer,
+    pipelines: Pipelines,
+    /// Pipelines used for deep zooms, with `deep_precision`.
+    deep_pipelines: Pipelines,
+    deep_precision: Precision,
+    /// The function and the number of its derivatives that the pipelines
+    /// were built for, or `None` for the polynomial.
+    function: Option<(String, u32)>,
+    pipeline_layout: wgpu::PipelineLayout,
+    bind_group_layout: wgpu::BindGroupLayout,
     bind_group: wgpu::BindGroup,
+    /// The attractors found for [`Basins::Attractors`] and the cycle report,
+    /// and the inputs of the first pass that they were found with.
+    attractors: Vec<Attractor>,
+    attractors_key: Vec<u8>,
+    final_position_target: FinalPositionTarget,
+    pending_attractors: Option<PendingAttractors>,
+}
+
+/// The pipelines of one precision.
+struct Pipelines {
+    /// Draws the fractal.
+    render: wgpu::RenderPipeline,
+    /// Writes where the orbits end up, for the first pass of
+    /// [`Basins::Attractors`] and the cycle report.
+    final_position: wgpu::RenderPipeline,
+}
+
+/// The texture that the first pass of [`Basins::Attractors`] and the cycle
+/// report renders into, and the buffer that it is read back with.
+struct FinalPositionTarget {
+    texture: wgpu::Texture,
+    texture_view: wgpu::TextureView,
+    buffer: wgpu::Buffer,
+}
+
+/// A first pass whose final positions are still being read back.
+struct PendingAttractors {
+    /// The inputs of the pass, which become `attractors_key` once the
+    /// attractors are found.
+    key: Vec<u8>,
+    config: Config,
+    mapping: Pin<Box<dyn Future<Output = Result<(), wgpu::BufferAsyncError>> + Send>>,
+}
+
+impl FinalPositionTarget {
+    /// Four `f32` per texel make rows that are already aligned.
+    const ROW_SIZE: u32 = attractor::GRID_SIZE * 16;
+
+    const SIZE: wgpu::Extent3d = wgpu::Extent3d {
+        width: attractor::GRID_SIZE,
+        height: attractor::GRID_SIZE,
+        depth_or_array_layers: 1,
+    };
+
+    fn new(gfx: &GraphicsContext) -> Self {
+        let texture = gfx.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("FractalRenderer.final_position_texture"),
+            size: Self::SIZE,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: FINAL_POSITION_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        });
+        let texture_view = texture.create_view(&Default::default());
+        let buffer = gfx.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("FractalRenderer.final_position_buffer"),
+            size: Self::ROW_SIZE as u64 * attractor::GRID_SIZE as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        Self {
+            texture,
+            texture_view,
+            buffer,
+        }
+    }
 }
 
 impl FractalRenderer {
@@ -20,22 +142,40 @@ impl FractalRenderer {
             .create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: Some("FractalRenderer.params_buffer"),
                 usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM,
-                contents: bytemuck::bytes_of(&ParamsAbi::from(&Config::default())),
+                contents: bytemuck::bytes_of(&ParamsAbi::zeroed()),
             });
+        let roots_buffer = StorageBuffer::new(gfx, "FractalRenderer.roots_buffer");
+        let coefficients_buffer = StorageBuffer::new(gfx, "FractalRenderer.coefficients_buffer");
+        let attractors_buffer = StorageBuffer::new(gfx, "FractalRenderer.attractors_buffer");
+        let storage_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
         let bind_group_layout =
             gfx.device
                 .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                     label: Some("FractalRenderer.bind_group_layout"),
-                    entries: &[wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
+                    entries: &[
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 0,
+                            visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                            ty: wgpu::BindingType::Buffer {
+                                ty: wgpu::BufferBindingType::Uniform,
+                                has_dynamic_offset: false,
+                                min_binding_size: None,
+                            },
+                            count: None,
                         },
-                        count: None,
-                    }],
+                        storage_entry(1),
+                        storage_entry(2),
+                        storage_entry(3),
+                    ],
                 });
         let pipeline_layout = gfx
             .device
@@ -44,59 +184,319 @@ impl FractalRenderer {
                 bind_group_layouts: &[&bind_group_layout],
                 push_constant_ranges: &[],
             });
-        let shader_module = gfx
-            .device
-            .create_shader_module(&wgpu::include_wgsl!("fractal.wgsl"));
-        let render_pipeline = gfx
+        let deep_precision = if gfx
             .device
-            .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-                label: Some("FractalRenderer.render_pipeline"),
-                layout: Some(&pipeline_layout),
-                vertex: wgpu::VertexState {
-                    module: &shader_module,
-                    entry_point: "main",
-                    buffers: &[],
-                },
-                primitive: Default::default(),
-                depth_stencil: None,
-                multisample: Default::default(),
-                fragment: Some(wgpu::FragmentState {
-                    module: &shader_module,
-                    entry_point: "main",
-                    targets: &[wgpu::ColorTargetState {
-                        format: gfx.render_format,
-                        blend: None,
-                        write_mask: Default::default(),
-                    }],
-                }),
-            });
-        let bind_group = gfx.device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("FractalRenderer.bind_group"),
-            layout: &bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: params_buffer.as_entire_binding(),
-            }],
-        });
+            .features()
+            .contains(wgpu::Features::SHADER_FLOAT64)
+        {
+            Precision::Double
+        } else {
+            Precision::DoubleSingle
+        };
+        let pipelines =
+            create_pipelines(gfx, &pipeline_layout, Precision::Single, NO_FUNCTION_WGSL);
+        let deep_pipelines =
+            create_pipelines(gfx, &pipeline_layout, deep_precision, NO_FUNCTION_WGSL);
+        let bind_group = create_bind_group(
+            gfx,
+            &bind_group_layout,
+            &params_buffer,
+            [&roots_buffer, &coefficients_buffer, &attractors_buffer],
+        );
         Self {
             gfx: gfx.clone(),
             params_buffer,
-            render_pipeline,
+            roots_buffer,
+            coefficients_buffer,
+            attractors_buffer,
+            pipelines,
+            deep_pipelines,
+            deep_precision,
+            function: None,
+            pipeline_layout,
+            bind_group_layout,
             bind_group,
+            attractors: Vec::new(),
+            attractors_key: Vec::new(),
+            final_position_target: FinalPositionTarget::new(gfx),
+            pending_attractors: None,
         }
     }
 
+    /// Rebuilds the pipelines if the function in `config`, or the number of
+    /// derivatives that the method needs from it, has changed.
+    fn update_function(&mut self, config: &Config) {
+        let order = config.method.order(config.householder_order);
+        let function = config
+            .function
+            .as_ref()
+            .map(|function| (function.source().to_owned(), order));
+        if function == self.function {
+            return;
+        }
+        let source = match &config.function {
+            Some(function) => function.taylor(order as usize).to_wgsl(),
+            None => NO_FUNCTION_WGSL.to_owned(),
+        };
+        self.pipelines =
+            create_pipelines(&self.gfx, &self.pipeline_layout, Precision::Single, &source);
+        self.deep_pipelines = create_pipelines(
+            &self.gfx,
+            &self.pipeline_layout,
+            self.deep_precision,
+            &source,
+        );
+        self.function = function;
+        // The key doesn't cover the function.
+        self.attractors_key.clear();
+        if let Some(pending) = &mut self.pending_attractors {
+            pending.key.clear();
+        }
+    }
+
+    /// The attractors around the camera in the last frame, which are only
+    /// found with [`Basins::Attractors`] or if cycles are detected.
+    pub fn attractors(&self) -> &[Attractor] {
+        &self.attractors
+    }
+
+    fn pipelines(&self, precision: Precision) -> &Pipelines {
+        if precision == Precision::Single {
+            &self.pipelines
+        } else {
+            &self.deep_pipelines
+        }
+    }
+
+    /// Uploads the parameters and the roots and coefficients of the
+    /// polynomial for drawing `region`.
+    fn write_buffers(&mut self, config: &Config, region: &Region) {
+        let mut params = ParamsAbi::new(config, region);
+        params.num_attractors = match config.basins {
+            Basins::NearestRoot => 0,
+            Basins::Attractors => {
+                self.attractors.iter().map(Attractor::period).sum::<usize>() as u32
+            }
+        };
+        self.gfx
+            .queue
+            .write_buffer(&self.params_buffer, 0, bytemuck::bytes_of(&params));
+        let roots: Vec<RootAbi> = config.roots.iter().map(RootAbi::from).collect();
+        let coefficients: Vec<[f32; 4]> = config
+            .polynomial()
+            .into_iter()
+            .map(|coef| {
+                let (hi, lo) = split(DVec2::new(coef.re, coef.im));
+                [hi[0], hi[1], lo[0], lo[1]]
+            })
+            .collect();
+        let attractors: Vec<AttractorAbi> = self
+            .attractors
+            .iter()
+            .flat_map(|attractor| {
+                attractor.points.iter().map(move |point| AttractorAbi {
+                    color: attractor.color.into(),
+                    position: [point.re as f32, point.im as f32],
+                    period: attractor.period() as u32,
+                    _padding: [0; 4],
+                })
+            })
+            .collect();
+        let roots_resized = self
+            .roots_buffer
+            .write(&self.gfx, bytemuck::cast_slice(&roots));
+        let coefficients_resized = self
+            .coefficients_buffer
+            .write(&self.gfx, bytemuck::cast_slice(&coefficients));
+        let attractors_resized = self
+            .attractors_buffer
+            .write(&self.gfx, bytemuck::cast_slice(&attractors));
+        if roots_resized || coefficients_resized || attractors_resized {
+            self.bind_group = create_bind_group(
+                &self.gfx,
+                &self.bind_group_layout,
+                &self.params_buffer,
+                [
+                    &self.roots_buffer,
+                    &self.coefficients_buffer,
+                    &self.attractors_buffer,
+                ],
+            );
+        }
+    }
+
+    /// Updates the function and the attractors for drawing `config`.
+    ///
+    /// With `wait`, this blocks until the attractors for `config` are found.
+    /// Otherwise the previous ones are kept until they are, so that the GPU
+    /// is never waited for while drawing frames.
+    fn prepare(&mut self, config: &Config, wait: bool) {
+        self.update_function(config);
+        if config.basins == Basins::Attractors || config.max_cycle_period > 0 {
+            if let Err(err) = self.update_attractors(config, wait) {
+                log::error!("{:#}", err);
+            }
+        } else {
+            self.attractors.clear();
+            self.attractors_key.clear();
+        }
+    }
+
+    /// Finds the attractors around the camera by rendering where the
+    /// orbits end up on a grid over [`attractor::sample_region`], unless the
+    /// inputs haven't changed since the last time.
+    ///
+    /// The final positions are read back asynchronously, and only one pass is
+    /// in flight at a time. With `wait`, this blocks until the GPU has
+    /// finished the pass instead.
+    fn update_attractors(&mut self, config: &Config, wait: bool) -> anyhow::Result<()> {
+        self.finish_attractors(wait)?;
+        if self.pending_attractors.is_some() {
+            return Ok(());
+        }
+        let region = attractor::sample_region(config);
+        let mut key = bytemuck::bytes_of(&ParamsAbi::new(config, &region)).to_vec();
+        for root in &config.roots {
+            key.extend_from_slice(bytemuck::bytes_of(&RootAbi::from(root)));
+        }
+        if key == self.attractors_key {
+            return Ok(());
+        }
+        // The first pass doesn't read the attractors, so there is no need to
+        // clear the old ones.
+        self.write_buffers(config, &region);
+
+        let target = &self.final_position_target;
+        let grid_size = Vec2::splat(attractor::GRID_SIZE as f32);
+        let precision = self.precision(&region, grid_size);
+        let mut encoder = self.gfx.device.create_command_encoder(&Default::default());
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("FractalRenderer.final_position_pass"),
+                color_attachments: &[wgpu::RenderPassColorAttachment {
+                    view: &target.texture_view,
+                    resolve_target: None,
+                    ops: Default::default(),
+                }],
+                depth_stencil_attachment: None,
+            });
+            render_pass.set_pipeline(&self.pipelines(precision).final_position);
+            render_pass.set_bind_group(0, &self.bind_group, &[]);
+            render_pass.draw(0..6, 0..1);
+        }
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                texture: &target.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &target.buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: NonZeroU32::new(FinalPositionTarget::ROW_SIZE),
+                    rows_per_image: None,
+                },
+            },
+            FinalPositionTarget::SIZE,
+        );
+        self.gfx.queue.submit([encoder.finish()]);
+
+        let mapping = target.buffer.slice(..).map_async(wgpu::MapMode::Read);
+        self.pending_attractors = Some(PendingAttractors {
+            key,
+            config: config.clone(),
+            mapping: Box::pin(mapping),
+        });
+        if wait {
+            self.finish_attractors(true)?;
+        }
+        Ok(())
+    }
+
+    /// Finds the attractors from the pending first pass, if its final
+    /// positions have been read back. With `wait`, this blocks until they are.
+    fn finish_attractors(&mut self, wait: bool) -> anyhow::Result<()> {
+        let mut pending = match self.pending_attractors.take() {
+            Some(pending) => pending,
+            None => return Ok(()),
+        };
+        let result = if wait {
+            self.gfx.device.poll(wgpu::Maintain::Wait);
+            block_on(&mut pending.mapping)
+        } else {
+            self.gfx.device.poll(wgpu::Maintain::Poll);
+            let mut context = task::Context::from_waker(Waker::noop());
+            match pending.mapping.as_mut().poll(&mut context) {
+                Poll::Ready(result) => result,
+                Poll::Pending => {
+                    self.pending_attractors = Some(pending);
+                    return Ok(());
+                }
+            }
+        };
+        result.context("failed to read back final positions")?;
+
+        let buffer = &self.final_position_target.buffer;
+        let slice = buffer.slice(..);
+        let samples: Vec<Sample> = bytemuck::cast_slice::<u8, [f32; 4]>(&slice.get_mapped_range())
+            .iter()
+            .map(|&[re, im, converged, _]| Sample {
+                position: Complex64::new(re as f64, im as f64),
+                converged: converged > 0.5,
+            })
+            .collect();
+        buffer.unmap();
+
+        self.attractors = attractor::find(&pending.config, &samples);
+        self.attractors_key = pending.key;
+        Ok(())
+    }
+
+    /// The precision that is used to draw `region` onto an image of size
+    /// `image_size`.
+    ///
+    /// Double precision is a lot slower, so it is only used once the pixels
+    /// get too small for single precision.
+    pub fn precision(&self, region: &Region, image_size: Vec2) -> Precision {
+        if region.is_resolved_by(f32::EPSILON as f64, image_size) {
+            Precision::Single
+        } else {
+            self.deep_precision
+        }
+    }
+
+    /// Draws the region visible through the camera in `config` onto the
+    /// surface texture `frame_view` of size `image_size`, returning the
+    /// precision that was used.
     pub fn draw(
         &mut self,
         encoder: &mut wgpu::CommandEncoder,
         frame_view: &wgpu::TextureView,
         config: &Config,
+        image_size: Vec2,
+    ) -> Precision {
+        let region = config.camera.region(image_size);
+        let precision = self.precision(&region, image_size);
+        self.prepare(config, false);
+        self.draw_region(encoder, frame_view, config, &region, precision);
+        precision
+    }
+
+    /// Draws the given region of the complex plane with the given precision,
+    /// ignoring the camera in `config`, after [`FractalRenderer::prepare`].
+    fn draw_region(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        frame_view: &wgpu::TextureView,
+        config: &Config,
+        region: &Region,
+        precision: Precision,
     ) {
-        self.gfx.queue.write_buffer(
-            &self.params_buffer,
-            0,
-            bytemuck::bytes_of(&ParamsAbi::from(config)),
-        );
+        // Uploaded after the first pass was submitted, so it doesn't see them.
+        self.write_buffers(config, region);
+
         let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
             label: Some("FractalRenderer.render_pass"),
             color_attachments: &[wgpu::RenderPassColorAttachment {
@@ -106,27 +506,307 @@ impl FractalRenderer {
             }],
             depth_stencil_attachment: None,
         });
-        render_pass.set_pipeline(&self.render_pipeline);
+        render_pass.set_pipeline(&self.pipelines(precision).render);
         render_pass.set_bind_group(0, &self.bind_group, &[]);
         render_pass.draw(0..6, 0..1);
     }
+
+    /// The largest width and height supported by [`FractalRenderer::render_image`].
+    pub fn max_image_size(&self) -> u32 {
+        self.gfx.device.limits().max_texture_dimension_2d
+    }
+
+    /// Renders the given region into an offscreen texture of the given size and
+    /// reads the result back into an image.
+    ///
+    /// This blocks until the GPU has finished rendering.
+    pub fn render_image(
+        &mut self,
+        config: &Config,
+        region: &Region,
+        width: u32,
+        height: u32,
+    ) -> anyhow::Result<Image> {
+        let max_size = self.max_image_size();
+        ensure!(
+            width > 0 && height > 0 && width <= max_size && height <= max_size,
+            "image size {}x{} is not supported, must be at most {}x{}",
+            width,
+            height,
+            max_size,
+            max_size
+        );
+
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let texture = self.gfx.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("FractalRenderer.image_texture"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: self.gfx.render_format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        });
+        let texture_view = texture.create_view(&Default::default());
+
+        // Rows in the readback buffer need to be aligned, so they may be
+        // padded past the end of the image row.
+        let row_size = width * 4;
+        let alignment = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_row_size = row_size.div_ceil(alignment) * alignment;
+        let buffer = self.gfx.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("FractalRenderer.image_buffer"),
+            size: padded_row_size as u64 * height as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let precision = self.precision(region, Vec2::new(width as f32, height as f32));
+        self.prepare(config, true);
+        let mut encoder = self.gfx.device.create_command_encoder(&Default::default());
+        self.draw_region(&mut encoder, &texture_view, config, region, precision);
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: NonZeroU32::new(padded_row_size),
+                    rows_per_image: None,
+                },
+            },
+            size,
+        );
+        self.gfx.queue.submit([encoder.finish()]);
+
+        let slice = buffer.slice(..);
+        let mapping = slice.map_async(wgpu::MapMode::Read);
+        self.gfx.device.poll(wgpu::Maintain::Wait);
+        block_on(mapping).context("failed to read back image")?;
+
+        let mut data = Vec::with_capacity(row_size as usize * height as usize);
+        for row in slice
+            .get_mapped_range()
+            .chunks_exact(padded_row_size as usize)
+        {
+            data.extend_from_slice(&row[..row_size as usize]);
+        }
+        buffer.unmap();
+
+        Ok(Image {
+            width,
+            height,
+            data,
+        })
+    }
+}
+
+/// Stands in for the code generated by [`crate::expr::Taylor::to_wgsl`] if the
+/// polynomial is iterated.
+const NO_FUNCTION_WGSL: &str = "\
+let FUNCTION_DEFINED: bool = false;
+
+fn function_taylor(z: Complex) -> array<Complex, 17> {
+    var taylor: array<Complex, 17>;
+    return taylor;
+}
+";
+
+/// The format of the texture that the first pass renders the final positions
+/// into, see [`FractalRenderer::update_attractors`].
+const FINAL_POSITION_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba32Float;
+
+/// Creates the pipelines for the given precision, where `function_source` is
+/// the code from [`crate::expr::Taylor::to_wgsl`] or [`NO_FUNCTION_WGSL`].
+fn create_pipelines(
+    gfx: &GraphicsContext,
+    layout: &wgpu::PipelineLayout,
+    precision: Precision,
+    function_source: &str,
+) -> Pipelines {
+    let source = format!(
+        "{}\n{}\n{}\n{}",
+        precision.shader_prelude(),
+        include_str!("complex_functions.wgsl"),
+        function_source,
+        include_str!("fractal.wgsl")
+    );
+    let shader_module = gfx
+        .device
+        .create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("fractal.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Owned(source)),
+        });
+    let create_pipeline = |label, fragment_entry_point, format| {
+        gfx.device
+            .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(layout),
+                vertex: wgpu::VertexState {
+                    module: &shader_module,
+                    entry_point: "main",
+                    buffers: &[],
+                },
+                primitive: Default::default(),
+                depth_stencil: None,
+                multisample: Default::default(),
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader_module,
+                    entry_point: fragment_entry_point,
+                    targets: &[wgpu::ColorTargetState {
+                        format,
+                        blend: None,
+                        write_mask: Default::default(),
+                    }],
+                }),
+            })
+    };
+    Pipelines {
+        render: create_pipeline("FractalRenderer.render_pipeline", "main", gfx.render_format),
+        final_position: create_pipeline(
+            "FractalRenderer.final_position_pipeline",
+            "final_position",
+            FINAL_POSITION_FORMAT,
+        ),
+    }
+}
+
+/// Creates the bind group with the uniform buffer and the storage buffers
+/// for the roots, coefficients and attractors, in binding order.
+fn create_bind_group(
+    gfx: &GraphicsContext,
+    layout: &wgpu::BindGroupLayout,
+    params_buffer: &wgpu::Buffer,
+    storage_buffers: [&StorageBuffer; 3],
+) -> wgpu::BindGroup {
+    let mut entries = vec![wgpu::BindGroupEntry {
+        binding: 0,
+        resource: params_buffer.as_entire_binding(),
+    }];
+    for (binding, storage_buffer) in (1..).zip(storage_buffers) {
+        entries.push(wgpu::BindGroupEntry {
+            binding,
+            resource: storage_buffer.buffer.as_entire_binding(),
+        });
+    }
+    gfx.device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("FractalRenderer.bind_group"),
+        layout,
+        entries: &entries,
+    })
 }
 
-fn complex_mul(a: Vec2, b: Vec2) -> Vec2 {
-    Vec2::new(a.x * b.x - a.y * b.y, a.x * b.y + a.y * b.x)
+/// A storage buffer that is reallocated whenever its contents outgrow it.
+struct StorageBuffer {
+    label: &'static str,
+    buffer: wgpu::Buffer,
+    capacity: u64,
+}
+
+impl StorageBuffer {
+    const MIN_CAPACITY: u64 = 256;
+
+    fn new(gfx: &GraphicsContext, label: &'static str) -> Self {
+        Self {
+            label,
+            buffer: Self::create_buffer(gfx, label, Self::MIN_CAPACITY),
+            capacity: Self::MIN_CAPACITY,
+        }
+    }
+
+    fn create_buffer(gfx: &GraphicsContext, label: &str, capacity: u64) -> wgpu::Buffer {
+        gfx.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size: capacity,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        })
+    }
+
+    /// Uploads `data` to the start of the buffer.
+    ///
+    /// Returns `true` if the buffer had to be reallocated, in which case any
+    /// bind groups referring to it need to be recreated.
+    fn write(&mut self, gfx: &GraphicsContext, data: &[u8]) -> bool {
+        let resized = data.len() as u64 > self.capacity;
+        if resized {
+            self.capacity = (data.len() as u64).next_power_of_two();
+            self.buffer = Self::create_buffer(gfx, self.label, self.capacity);
+        }
+        gfx.queue.write_buffer(&self.buffer, 0, data);
+        resized
+    }
 }
 
 #[derive(Debug, Clone, Copy, Pod, Zeroable)]
 #[repr(C)]
 struct ParamsAbi {
     num_iterations: u32,
-    _padding: [u8; 4],
-    camera_position: [f32; 2],
-    camera_zoom: f32,
     num_roots: u32,
-    _padding_2: [u8; 8],
-    roots: [RootAbi; MAX_ROOTS],
-    coefficients: [[f32; 2]; MAX_COEFFICIENTS],
+    camera_position: [f32; 2],
+    camera_scale: [f32; 2],
+    coloring: u32,
+    epsilon: f32,
+    non_convergence_color: [f32; 4],
+    camera_position_lo: [f32; 2],
+    relaxation: [f32; 2],
+    method: u32,
+    order: u32,
+    nova_c: [f32; 2],
+    nova: u32,
+    degree: u32,
+    basins: u32,
+    num_attractors: u32,
+    attractor_radius: f32,
+    max_cycle_period: u32,
+    _padding: [u8; 8],
+    cycle_color: [f32; 4],
+}
+
+impl ParamsAbi {
+    fn new(config: &Config, region: &Region) -> Self {
+        let (camera_position, camera_position_lo) = split(region.center);
+        Self {
+            num_iterations: config.num_iterations,
+            num_roots: config.roots.len() as u32,
+            camera_position,
+            camera_scale: region.half_size.as_vec2().into(),
+            coloring: config.coloring as u32,
+            epsilon: config.epsilon,
+            non_convergence_color: config.non_convergence_color.into(),
+            camera_position_lo,
+            relaxation: config.relaxation.into(),
+            method: config.method as u32,
+            order: config.method.order(config.householder_order),
+            nova_c: config.nova_c.into(),
+            nova: config.nova as u32,
+            degree: config.polynomial().len() as u32 - 1,
+            basins: config.basins as u32,
+            // Set by `FractalRenderer::write_buffers`.
+            num_attractors: 0,
+            attractor_radius: attractor::radius(config) as f32,
+            max_cycle_period: config.max_cycle_period,
+            _padding: [0; 8],
+            cycle_color: config.cycle_color.into(),
+        }
+    }
+}
+
+/// Splits a double precision vector into its value rounded to single
+/// precision, and the rounding error.
+fn split(v: DVec2) -> ([f32; 2], [f32; 2]) {
+    let hi = v.as_vec2();
+    let lo = (v - hi.as_dvec2()).as_vec2();
+    (hi.into(), lo.into())
 }
 
 #[derive(Debug, Clone, Copy, Pod, Zeroable)]
@@ -134,57 +814,26 @@ struct ParamsAbi {
 struct RootAbi {
     color: [f32; 4],
     position: [f32; 2],
-    _padding: [u8; 8],
+    multiplicity: u32,
+    _padding: [u8; 4],
 }
 
-impl From<&Config> for ParamsAbi {
-    fn from(config: &Config) -> Self {
-        assert!(
-            config.roots.len() < MAX_ROOTS,
-            "too many roots, must be at most {}",
-            MAX_ROOTS
-        );
-
-        let mut roots = [RootAbi::zeroed(); MAX_ROOTS];
-        let mut coefficients = [<[f32; 2]>::zeroed(); MAX_COEFFICIENTS];
-
-        for (slot, root) in roots.iter_mut().zip(&config.roots) {
-            slot.position = root.position.into();
-            slot.color = root.color.into();
-        }
-
-        // Compute coefficients:
-        let mut p = [Vec2::ZERO; MAX_COEFFICIENTS];
-        p[0] = Vec2::new(1.0, 0.0);
-        for root in &config.roots {
-            let mut q = p.clone();
-            // Multiply p by x (shift forward)
-            for i in (1..MAX_COEFFICIENTS).rev() {
-                p[i] = p[i - 1];
-            }
-            p[0] = Vec2::ZERO;
-            // Multiply q by root
-            for term in &mut q {
-                *term = complex_mul(*term, root.position)
-            }
-            // Element-wise subtract q from p
-            for (a, b) in p.iter_mut().zip(&q) {
-                *a -= *b;
-            }
-        }
-        for (slot, coef) in coefficients.iter_mut().zip(p) {
-            *slot = coef.into();
-        }
-
+impl From<&RootConfig> for RootAbi {
+    fn from(root: &RootConfig) -> Self {
         Self {
+            color: root.color.into(),
+            position: root.position.into(),
+            multiplicity: root.multiplicity.max(1),
             _padding: [0; 4],
-            _padding_2: [0; 8],
-            num_iterations: config.num_iterations,
-            camera_position: config.camera.position.into(),
-            camera_zoom: config.camera.zoom,
-            num_roots: config.roots.len() as u32,
-            roots,
-            coefficients,
         }
     }
 }
+
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+#[repr(C)]
+struct AttractorAbi {
+    color: [f32; 4],
+    position: [f32; 2],
+    period: u32,
+    _padding: [u8; 4],
+}
@@ -2,13 +2,20 @@
 // texture.
 //
 // Complex numbers are used widely in the implementation of Newton's Fractal.
-// In this program, the convention is to represent complex numbers as 2D
-// vectors, i.e. `vec2<f32>`. A given vector `a` represents the point
-// `a.x + i * a.y` in the complex plane.
-// 
-// Note of caution: The standard multiplication operator `a * b` does not
-// implement complex multiplication; instead, the `complex_mul` function must
-// be used.
+// Their representation depends on the precision that the shader is built for,
+// so they have the opaque type `Complex`, which is defined together with the
+// `c_*` arithmetic functions in one of the `complex_*.wgsl` files. That file is
+// prepended to this one when the shader module is created.
+//
+// If a function was entered instead of the polynomial, the code that computes
+// its derivatives is generated by `expr::Taylor::to_wgsl` and placed between
+// the two files, after `complex_functions.wgsl`. It defines `FUNCTION_DEFINED`
+// and `function_taylor`.
+//
+// Values that don't need the extra precision, like the root positions, are
+// still passed as 2D vectors, i.e. `vec2<f32>`, and converted with
+// `c_from_f32`. A given vector `a` represents the point `a.x + i * a.y` in the
+// complex plane.
 
 var<private> position_array: array<vec2<f32>, 6> = array<vec2<f32>, 6>(
     vec2<f32>(-1.0, -1.0),
@@ -20,8 +27,28 @@ var<private> position_array: array<vec2<f32>, 6> = array<vec2<f32>, 6>(
     vec2<f32>(1.0, 1.0),
 );
 
-let MAX_ROOTS: u32 = 10u;
-let MAX_COEFFICIENTS: u32 = 11u; // 1u + MAX_ROOTS
+// Values of `Params.coloring`, see `config::Coloring`.
+let COLORING_FLAT: u32 = 0u;
+let COLORING_ITERATION_COUNT: u32 = 1u;
+let COLORING_SMOOTH_ITERATION: u32 = 2u;
+let COLORING_ROOT_DISTANCE: u32 = 3u;
+
+// Values of `Params.method`, see `config::Method`.
+let METHOD_NEWTON: u32 = 0u;
+let METHOD_HALLEY: u32 = 1u;
+let METHOD_SCHRODER: u32 = 2u;
+let METHOD_CHEBYSHEV: u32 = 3u;
+let METHOD_HOUSEHOLDER: u32 = 4u;
+let METHOD_MODIFIED_NEWTON: u32 = 5u;
+
+// Values of `Params.nova`, see `config::Nova`.
+let NOVA_OFF: u32 = 0u;
+let NOVA_MANDELBROT: u32 = 1u;
+let NOVA_JULIA: u32 = 2u;
+
+// Values of `Params.basins`, see `config::Basins`.
+let BASINS_NEAREST_ROOT: u32 = 0u;
+let BASINS_ATTRACTORS: u32 = 1u;
 
 struct Root {
     // Color corresponding to this root point. Used to indicate which pixels
@@ -31,66 +58,138 @@ struct Root {
     // Position of this root on the complex plane.
     position: vec2<f32>;
 
-    padding: vec2<f32>;
+    // How many times this root is repeated in the polynomial.
+    multiplicity: u32;
+
+    padding: u32;
 };
 [[block]] struct Params {
-    // How many Newton-Raphson iterations to perform.
+    // The maximum number of Newton-Raphson iterations to perform.
     num_iterations: u32;
 
+    num_roots: u32;
+
+    // Center of the rendered region of the complex plane. This is only the
+    // high part of the center, see `camera_position_lo`.
     camera_position: vec2<f32>;
 
-    camera_zoom: f32;
+    // Half of the width and height of the rendered region.
+    camera_scale: vec2<f32>;
 
-    num_roots: u32;
+    // How to shade the basins, one of the `COLORING_*` constants.
+    coloring: u32;
+
+    // Step size below which an iteration is considered to have converged.
+    epsilon: f32;
+
+    // Color of the points that did not converge within `num_iterations`.
+    non_convergence_color: vec4<f32>;
+
+    // Rounding error of `camera_position`, which is added back by the higher
+    // precision shaders.
+    camera_position_lo: vec2<f32>;
+
+    // Complex factor that each Newton step is multiplied with.
+    relaxation: vec2<f32>;
+
+    // The root-finding iteration, one of the `METHOD_*` constants.
+    method: u32;
+
+    // The number of derivatives used by the method, at most
+    // `config::MAX_HOUSEHOLDER_ORDER`.
+    order: u32;
 
-    // The roots of the polynomial, stored contiguously in the lower 0..num_roots elements.
-    roots: [[stride(32)]] array<Root, MAX_ROOTS>;
+    // The constant that is added in each iteration if `nova` is `NOVA_JULIA`.
+    nova_c: vec2<f32>;
 
-    // Coefficients of the polynomial when written in ascending-power form.
-    // The element at array index `i` specifies the coefficient of the term
-    // containing the `i` power. There should be `num_roots + 1` coefficients.
-    coefficients: [[stride(8)]] array<vec2<f32>, MAX_COEFFICIENTS>;
+    // The variant of the Nova fractal, one of the `NOVA_*` constants.
+    nova: u32;
+
+    // The degree of the polynomial, i.e. the sum of the root multiplicities.
+    degree: u32;
+
+    // How the basins are told apart, one of the `BASINS_*` constants.
+    basins: u32;
+
+    num_attractors: u32;
+
+    // Distance within which an orbit that did not converge is considered to be
+    // on an attracting cycle.
+    attractor_radius: f32;
+
+    // The longest period of the cycles that are detected in each orbit, or 0
+    // to not look for cycles.
+    max_cycle_period: u32;
+
+    // Color of the points whose orbit fell into a cycle.
+    cycle_color: vec4<f32>;
 };
 [[group(0), binding(0)]] var<uniform> params: Params;
 
+// The roots of the polynomial, stored contiguously in the lower
+// 0..num_roots elements.
+[[block]] struct Roots {
+    data: [[stride(32)]] array<Root>;
+};
+[[group(0), binding(1)]] var<storage, read> roots: Roots;
+
+// Coefficients of the polynomial when written in ascending-power form.
+// The element at array index `i` specifies the coefficient of the term
+// containing the `i` power. There should be `degree + 1` coefficients.
+//
+// Each coefficient is split into a high part in `xy` and its rounding error in
+// `zw`, like `camera_position`.
+[[block]] struct Coefficients {
+    data: [[stride(16)]] array<vec4<f32>>;
+};
+[[group(0), binding(2)]] var<storage, read> coefficients: Coefficients;
+
+// A point of an attractor, which the orbits were clustered into on the CPU,
+// see `attractor::find`.
+struct Attractor {
+    color: vec4<f32>;
+
+    position: vec2<f32>;
+
+    // The number of points of the cycle that this point belongs to, 1 for
+    // fixed points.
+    period: u32;
+
+    padding: u32;
+};
+
+// The points of the attractors, stored contiguously in the lower
+// 0..num_attractors elements. Only used with `BASINS_ATTRACTORS`.
+[[block]] struct Attractors {
+    data: [[stride(32)]] array<Attractor>;
+};
+[[group(0), binding(3)]] var<storage, read> attractors: Attractors;
+
 struct VertexOutput {
     [[builtin(position)]] position: vec4<f32>;
-    [[location(0)]] grid_position: vec2<f32>;
+    // Position in clip space, from (-1, -1) at the bottom left to (1, 1) at
+    // the top right.
+    [[location(0)]] clip_position: vec2<f32>;
 };
 
 [[stage(vertex)]]
 fn main([[builtin(vertex_index)]] vertex_index: u32) -> VertexOutput {
     var out: VertexOutput;
     out.position = vec4<f32>(position_array[vertex_index], 0.0, 1.0);
-    out.grid_position = position_array[vertex_index] / params.camera_zoom + params.camera_position; 
+    out.clip_position = position_array[vertex_index];
     return out;
 }
 
-// Performs complex multiplication of `a` and `b`.
-fn complex_mul(a: vec2<f32>, b: vec2<f32>) -> vec2<f32> {
-    return vec2<f32>(
-        a.x * b.x - a.y * b.y,
-        a.x * b.y + a.y * b.x,
-    );
-}
-
-// Conjugate of a given complex number `a`.
-fn conj(a: vec2<f32>) -> vec2<f32> {
-    return vec2<f32>(a.x, -a.y);
-}
-
-// Multiplicative inverse of a given complex number `a`.
-fn inverse(a: vec2<f32>) -> vec2<f32> {
-    return conj(a) / (a.x * a.x + a.y * a.y);
-}
-
 // Compute the vaolue of the polynomial specified by `params` at the point `z`.
 //
-// Uses the factored form of the polynomial: `(z - r1) * (z - r2) * ...`
-fn poly(z: vec2<f32>) -> vec2<f32> {
-    var product = vec2<f32>(1.0, 0.0);
+// Uses the factored form of the polynomial: `(z - r1)^m1 * (z - r2)^m2 * ...`
+fn poly(z: Complex) -> Complex {
+    var product = c_from_f32(vec2<f32>(1.0, 0.0));
     for (var i: u32 = 0u; i < params.num_roots; i = i + 1u) {
-        product = complex_mul(product, z - params.roots[i].position);
+        let factor = c_sub(z, c_from_f32(roots.data[i].position));
+        for (var j: u32 = 0u; j < roots.data[i].multiplicity; j = j + 1u) {
+            product = c_mul(product, factor);
+        }
     }
     return product;
 }
@@ -100,32 +199,319 @@ fn poly(z: vec2<f32>) -> vec2<f32> {
 //
 // Uses the ascending-powers form of the polynomial, `a0 + a1*z + a2*z^2 + ...`,
 // with the derivative being the power rule applied to each term: `a1 + 2*a2*z + ...`
-fn grad(z: vec2<f32>) -> vec2<f32> {
-    var sum = vec2<f32>(0.0, 0.0);
-    var z_power = vec2<f32>(1.0, 0.0);
-    for (var i: u32 = 0u; i < params.num_roots; i = i + 1u) {
-        sum = sum + f32(i + 1u) * complex_mul(params.coefficients[i + 1u], z_power);
-        z_power = complex_mul(z_power, z);
+fn grad(z: Complex) -> Complex {
+    var sum = c_from_f32(vec2<f32>(0.0, 0.0));
+    var z_power = c_from_f32(vec2<f32>(1.0, 0.0));
+    for (var i: u32 = 0u; i < params.degree; i = i + 1u) {
+        let coefficient = coefficients.data[i + 1u];
+        let term = c_mul(c_from_split(coefficient.xy, coefficient.zw), z_power);
+        sum = c_add(sum, c_scale(term, f32(i + 1u)));
+        z_power = c_mul(z_power, z);
     }
     return sum;
 }
 
-[[stage(fragment)]]
-fn main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
-    var position: vec2<f32> = in.grid_position;
-    for (var i: u32 = 0u; i < params.num_iterations; i = i + 1u) {
-        position = position - complex_mul(poly(position), inverse(grad(position)));
+// The index of the root that is closest to `z`. There must be at least one.
+fn nearest_root(z: Complex) -> u32 {
+    var nearest: u32 = 0u;
+    var min_distance: f32 = c_length(c_sub(z, c_from_f32(roots.data[0].position)));
+    for (var i: u32 = 1u; i < params.num_roots; i = i + 1u) {
+        let candidate_distance = c_length(c_sub(z, c_from_f32(roots.data[i].position)));
+        if (candidate_distance < min_distance) {
+            min_distance = candidate_distance;
+            nearest = i;
+        }
     }
+    return nearest;
+}
 
-    var color: vec4<f32> = params.roots[0].color;
-    var min_distance: f32 = distance(position, params.roots[0].position);
-    for (var i: u32 = 1u; i < params.num_roots; i = i + 1u) {
-        let candidate_distance = distance(position, params.roots[i].position);
+// The index of the attractor point that is closest to `z`. There must be at
+// least one.
+fn nearest_attractor(z: Complex) -> u32 {
+    var nearest: u32 = 0u;
+    var min_distance: f32 = c_length(c_sub(z, c_from_f32(attractors.data[0].position)));
+    for (var i: u32 = 1u; i < params.num_attractors; i = i + 1u) {
+        let candidate_distance = c_length(c_sub(z, c_from_f32(attractors.data[i].position)));
         if (candidate_distance < min_distance) {
             min_distance = candidate_distance;
-            color = params.roots[i].color;
+            nearest = i;
         }
     }
+    return nearest;
+}
+
+// Normalized Taylor coefficients `p^(k)(z) / k!` of the polynomial around
+// some point `z`, up to `params.order`. Filled by `compute_taylor`, or by
+// `function_taylor` for a function.
+var<private> taylor: array<Complex, 17>;
+
+// Taylor coefficients of `1 / p`, see `householder_step`.
+var<private> inverse_taylor: array<Complex, 17>;
+
+fn c_zero() -> Complex {
+    return c_from_f32(vec2<f32>(0.0, 0.0));
+}
 
-    return color;
-}
\ No newline at end of file
+fn c_one() -> Complex {
+    return c_from_f32(vec2<f32>(1.0, 0.0));
+}
+
+// Fills `taylor` for the point `z`.
+//
+// Uses Horner's method on the ascending-powers form, which computes all the
+// derivatives at once. The value and first derivative are replaced with the
+// results of `poly` and `grad`, to match Newton's method.
+fn compute_taylor(z: Complex) {
+    for (var k: u32 = 0u; k <= params.order; k = k + 1u) {
+        taylor[k] = c_zero();
+    }
+    for (var i: u32 = 0u; i <= params.degree; i = i + 1u) {
+        let coefficient = coefficients.data[params.degree - i];
+        for (var k: u32 = min(params.order, i); k > 0u; k = k - 1u) {
+            taylor[k] = c_add(c_mul(taylor[k], z), taylor[k - 1u]);
+        }
+        taylor[0] = c_add(c_mul(taylor[0], z), c_from_split(coefficient.xy, coefficient.zw));
+    }
+    taylor[0] = poly(z);
+    taylor[1] = grad(z);
+}
+
+// The step of Householder's method of order `params.order`, from `taylor`.
+fn householder_step() -> Complex {
+    let a0 = taylor[0];
+    // The Taylor coefficients of `1 / p` are `b_0 = 1 / a_0` and
+    // `b_k = -(a_1 b_(k-1) + a_2 b_(k-2) + ... + a_k b_0) / a_0`. They are
+    // scaled by `a_0^(k+1)` here, which keeps them in range as `a_0`
+    // approaches zero, and turns the division into a multiplication of `a_k`
+    // by `a_0^(k-1)`.
+    inverse_taylor[0] = c_one();
+    var a0_power = c_one();
+    for (var k: u32 = 1u; k <= params.order; k = k + 1u) {
+        taylor[k] = c_mul(taylor[k], a0_power);
+        a0_power = c_mul(a0_power, a0);
+        var sum = c_zero();
+        for (var j: u32 = 1u; j <= k; j = j + 1u) {
+            sum = c_add(sum, c_mul(taylor[j], inverse_taylor[k - j]));
+        }
+        inverse_taylor[k] = c_sub(c_zero(), sum);
+    }
+    return c_sub(c_zero(), c_div(c_mul(a0, inverse_taylor[params.order - 1u]), inverse_taylor[params.order]));
+}
+
+// The step of the root-finding method at the point `z`, without relaxation.
+//
+// Mirrors `cpu::method_step`.
+fn method_step(z: Complex) -> Complex {
+    if (FUNCTION_DEFINED) {
+        taylor = function_taylor(z);
+    } elseif (params.method == METHOD_NEWTON || params.method == METHOD_MODIFIED_NEWTON) {
+        taylor[0] = poly(z);
+        taylor[1] = grad(z);
+    } else {
+        compute_taylor(z);
+    }
+    if (params.method == METHOD_NEWTON) {
+        return c_div(taylor[0], taylor[1]);
+    } elseif (params.method == METHOD_MODIFIED_NEWTON) {
+        var multiplicity: u32 = 1u;
+        if (params.num_roots > 0u) {
+            multiplicity = roots.data[nearest_root(z)].multiplicity;
+        }
+        return c_scale(c_div(taylor[0], taylor[1]), f32(multiplicity));
+    } elseif (params.method == METHOD_HOUSEHOLDER) {
+        return householder_step();
+    }
+    let a0 = taylor[0];
+    let a1 = taylor[1];
+    let a2 = taylor[2];
+    if (params.method == METHOD_HALLEY) {
+        return c_div(c_mul(a0, a1), c_sub(c_mul(a1, a1), c_mul(a0, a2)));
+    } elseif (params.method == METHOD_SCHRODER) {
+        let a0a2 = c_mul(a0, a2);
+        return c_div(c_mul(a0, a1), c_sub(c_mul(a1, a1), c_add(a0a2, a0a2)));
+    }
+    // Chebyshev
+    let a1_squared = c_mul(a1, a1);
+    return c_div(c_mul(a0, c_add(a1_squared, c_mul(a0, a2))), c_mul(a1_squared, a1));
+}
+
+// Interpolates where between two consecutive steps the step size crossed the
+// convergence tolerance `params.epsilon`, on a logarithmic scale. Returns a value in `[0, 1]`,
+// where 1 means that `step_size` is right at the tolerance.
+fn smooth_fraction(last_step_size: f32, step_size: f32) -> f32 {
+    let last = log(last_step_size);
+    let current = log(max(step_size, 1e-30));
+    return clamp((log(params.epsilon) - last) / (current - last), 0.0, 1.0);
+}
+
+// The end of the orbit of a pixel, see `iterate`.
+struct Orbit {
+    position: Complex;
+
+    // Whether the step size dropped below the tolerance.
+    converged: bool;
+
+    // The period of the cycle that the orbit fell into, or 0 if none was
+    // detected.
+    period: u32;
+
+    // How many iterations it took for the step size to drop below the
+    // tolerance, or to detect the cycle, both as a whole number and
+    // interpolated between iterations.
+    iterations: f32;
+    smooth_iterations: f32;
+};
+
+// Iterates the pixel at `clip_position`. Mirrors `cpu::Params::iterate`.
+fn iterate(clip_position: vec2<f32>) -> Orbit {
+    // The offset from the center is small enough for single precision, only
+    // the sum needs the full precision.
+    let camera_position = c_from_split(params.camera_position, params.camera_position_lo);
+    let pixel = c_add(camera_position, c_from_f32(clip_position * params.camera_scale));
+    var position: Complex = pixel;
+    // The constant that is added in each iteration.
+    var c: Complex = c_zero();
+    if (params.nova == NOVA_MANDELBROT) {
+        // Start from the first root, which is a critical point of Newton's
+        // method, like `cpu::Params::critical_point`.
+        c = pixel;
+        position = c_zero();
+        if (params.num_roots > 0u) {
+            position = c_from_f32(roots.data[0].position);
+        }
+    } elseif (params.nova == NOVA_JULIA) {
+        c = c_from_f32(params.nova_c);
+    }
+    var iterations: f32 = f32(params.num_iterations);
+    var smooth_iterations: f32 = iterations;
+    var converged: bool = false;
+    var last_step_size: f32 = 0.0;
+    // The point that the orbit is compared with to detect cycles, and the
+    // iteration that it was reached at. It moves along every
+    // `max_cycle_period` iterations, so that any cycle up to that period is
+    // caught once the orbit has settled on it.
+    var cycle_start: Complex = position;
+    var cycle_start_iteration: u32 = 0u;
+    var period: u32 = 0u;
+    for (var i: u32 = 0u; i < params.num_iterations; i = i + 1u) {
+        let step = c_sub(c_mul(c_from_f32(params.relaxation), method_step(position)), c);
+        position = c_sub(position, step);
+
+        let step_size = c_length(step);
+        if (!converged && step_size < params.epsilon) {
+            converged = true;
+            iterations = f32(i + 1u);
+            smooth_iterations = iterations;
+            if (i > 0u) {
+                smooth_iterations = f32(i) + smooth_fraction(last_step_size, step_size);
+            }
+            // Distance shading depends on the position after the last
+            // iteration, everything else can stop here.
+            if (params.coloring != COLORING_ROOT_DISTANCE) {
+                break;
+            }
+        }
+        // Returning to an earlier point within the tolerance means the orbit
+        // is on a cycle. Fixed points converge before they get here.
+        if (!converged && params.max_cycle_period > 0u) {
+            let steps = i + 1u - cycle_start_iteration;
+            if (c_length(c_sub(position, cycle_start)) < params.epsilon) {
+                period = steps;
+                iterations = f32(i + 1u);
+                smooth_iterations = iterations;
+                break;
+            }
+            if (steps == params.max_cycle_period) {
+                cycle_start = position;
+                cycle_start_iteration = i + 1u;
+            }
+        }
+        last_step_size = step_size;
+    }
+    return Orbit(position, converged, period, iterations, smooth_iterations);
+}
+
+[[stage(fragment)]]
+fn main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let orbit = iterate(in.clip_position);
+    let position = orbit.position;
+
+    if (orbit.period > 0u) {
+        // The cycle is only known if the attractors were clustered, otherwise
+        // all cycles share a color.
+        var color: vec4<f32> = params.cycle_color;
+        if (params.num_attractors > 0u) {
+            let attractor = attractors.data[nearest_attractor(position)];
+            let distance = c_length(c_sub(position, c_from_f32(attractor.position)));
+            if (attractor.period > 1u && distance < params.attractor_radius) {
+                color = attractor.color;
+            }
+        }
+        var brightness: f32 = 1.0;
+        if (params.coloring == COLORING_ITERATION_COUNT
+            || params.coloring == COLORING_SMOOTH_ITERATION) {
+            brightness = 1.0 - orbit.iterations / f32(params.num_iterations);
+        }
+        return vec4<f32>(color.rgb * brightness, color.a);
+    }
+
+    // This also catches iterations that ended up at infinity or NaN, since the
+    // step size comparison is false for those.
+    if (!orbit.converged) {
+        // Without cycle detection, orbits that ended up close to an attracting
+        // cycle still get its color.
+        if (params.num_attractors > 0u) {
+            let attractor = attractors.data[nearest_attractor(position)];
+            let distance = c_length(c_sub(position, c_from_f32(attractor.position)));
+            if (attractor.period > 1u && distance < params.attractor_radius) {
+                return attractor.color;
+            }
+        }
+        return params.non_convergence_color;
+    }
+
+    // Without roots or attractors, as when none were found for a function,
+    // there is no color to use.
+    var color: vec4<f32>;
+    var target: vec2<f32>;
+    if (params.basins == BASINS_ATTRACTORS) {
+        if (params.num_attractors == 0u) {
+            return params.non_convergence_color;
+        }
+        let attractor = attractors.data[nearest_attractor(position)];
+        color = attractor.color;
+        target = attractor.position;
+    } else {
+        if (params.num_roots == 0u) {
+            return params.non_convergence_color;
+        }
+        let root = roots.data[nearest_root(position)];
+        color = root.color;
+        target = root.position;
+    }
+    let min_distance = c_length(c_sub(position, c_from_f32(target)));
+
+    var brightness: f32 = 1.0;
+    if (params.coloring == COLORING_ITERATION_COUNT) {
+        brightness = 1.0 - orbit.iterations / f32(params.num_iterations);
+    } elseif (params.coloring == COLORING_SMOOTH_ITERATION) {
+        brightness = 1.0 - orbit.smooth_iterations / f32(params.num_iterations);
+    } elseif (params.coloring == COLORING_ROOT_DISTANCE) {
+        brightness = clamp(log(min_distance) / log(params.epsilon), 0.0, 1.0);
+    }
+
+    return vec4<f32>(color.rgb * brightness, color.a);
+}
+
+// The first pass of `BASINS_ATTRACTORS` and of the cycle report, which writes
+// where the orbit of the pixel ends up, and 1 in `z` if it converged. The CPU
+// clusters these positions into attractors.
+[[stage(fragment)]]
+fn final_position(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let orbit = iterate(in.clip_position);
+    var converged: f32 = 0.0;
+    if (orbit.converged) {
+        converged = 1.0;
+    }
+    return vec4<f32>(c_to_f32(orbit.position), converged, 0.0);
+}
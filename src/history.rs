@@ -0,0 +1,286 @@
+//! Undo and redo of config changes.
+//!
+//! The history keeps a snapshot of the config before each change, labeled
+//! with a description of the change. Changes that are sent continuously while
+//! dragging, like moving a root or panning the camera, are merged into one
+//! entry as long as they follow each other closely.
+
+use std::time::{Duration, Instant};
+
+use crate::config::{Config, ConfigChangeEvent};
+
+/// The number of changes that can be undone. Older ones are forgotten.
+const MAX_ENTRIES: usize = 200;
+
+/// Continuous changes with the same label that follow each other within this
+/// time are merged.
+const COALESCE_TIME: Duration = Duration::from_millis(500);
+
+struct Entry {
+    /// The config on the other side of the change, i.e. before it on the undo
+    /// stack and after it on the redo stack.
+    config: Config,
+    label: String,
+}
+
+/// The undo and redo stacks of config snapshots.
+#[derive(Default)]
+pub struct History {
+    undo: Vec<Entry>,
+    /// The most recently undone change is at the end.
+    redo: Vec<Entry>,
+    /// When the last change was recorded, if it can still be merged with the
+    /// next one.
+    last_change: Option<Instant>,
+}
+
+impl History {
+    /// Records that `event` is about to be applied to `config`.
+    pub fn record(&mut self, config: &Config, event: &ConfigChangeEvent) {
+        let (label, continuous) = describe(event);
+        let now = Instant::now();
+        let coalesce = continuous
+            && self
+                .last_change
+                .is_some_and(|last_change| now - last_change < COALESCE_TIME)
+            && self.undo.last().is_some_and(|entry| entry.label == label);
+        if coalesce {
+            self.redo.clear();
+        } else {
+            self.push(config, label);
+        }
+        self.last_change = continuous.then_some(now);
+    }
+
+    /// Records that `config` is about to be replaced as a whole, e.g. by
+    /// loading a preset.
+    pub fn push(&mut self, config: &Config, label: impl Into<String>) {
+        self.redo.clear();
+        self.undo.push(Entry {
+            config: config.clone(),
+            label: label.into(),
+        });
+        if self.undo.len() > MAX_ENTRIES {
+            self.undo.remove(0);
+        }
+        self.last_change = None;
+    }
+
+    /// Undoes the last change to `config`, returning the config before it.
+    pub fn undo(&mut self, config: &Config) -> Option<Config> {
+        let entry = self.undo.pop()?;
+        self.redo.push(Entry {
+            config: config.clone(),
+            label: entry.label,
+        });
+        self.last_change = None;
+        Some(entry.config)
+    }
+
+    /// Redoes the last undone change to `config`, returning the config after
+    /// it.
+    pub fn redo(&mut self, config: &Config) -> Option<Config> {
+        let entry = self.redo.pop()?;
+        self.undo.push(Entry {
+            config: config.clone(),
+            label: entry.label,
+        });
+        self.last_change = None;
+        Some(entry.config)
+    }
+
+    /// Undoes or redoes changes to `config` until `position` changes are
+    /// applied, see [`History::labels`].
+    pub fn go_to(&mut self, config: &Config, position: usize) -> Option<Config> {
+        let mut result: Option<Config> = None;
+        while self.position() > position {
+            result = self.undo(result.as_ref().unwrap_or(config));
+        }
+        while self.position() < position && self.can_redo() {
+            result = self.redo(result.as_ref().unwrap_or(config));
+        }
+        result
+    }
+
+    /// The labels of all changes in order, including the undone ones.
+    pub fn labels(&self) -> impl Iterator<Item = &str> {
+        self.undo
+            .iter()
+            .chain(self.redo.iter().rev())
+            .map(|entry| entry.label.as_str())
+    }
+
+    /// The number of changes that are applied, the others in
+    /// [`History::labels`] have been undone.
+    pub fn position(&self) -> usize {
+        self.undo.len()
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+}
+
+/// The label of the history entry for `event`, and whether it is sent
+/// continuously while dragging a widget or the view.
+fn describe(event: &ConfigChangeEvent) -> (String, bool) {
+    match *event {
+        ConfigChangeEvent::NumIterations(_) => ("Iterations".into(), true),
+        ConfigChangeEvent::Method(_) => ("Method".into(), false),
+        ConfigChangeEvent::HouseholderOrder(_) => ("Householder order".into(), true),
+        ConfigChangeEvent::Relaxation(_) => ("Relaxation".into(), true),
+        ConfigChangeEvent::Nova(_) => ("Nova".into(), false),
+        ConfigChangeEvent::NovaC(_) => ("Nova c".into(), true),
+        ConfigChangeEvent::Epsilon(_) => ("Epsilon".into(), true),
+        ConfigChangeEvent::Coloring(_) => ("Coloring".into(), false),
+        ConfigChangeEvent::Basins(_) => ("Basins".into(), false),
+        ConfigChangeEvent::NonConvergenceColor(_) => ("Non-convergence color".into(), true),
+        ConfigChangeEvent::MaxCyclePeriod(_) => ("Max cycle period".into(), true),
+        ConfigChangeEvent::CycleColor(_) => ("Cycle color".into(), true),
+        ConfigChangeEvent::AddRoot => ("Add root".into(), false),
+        ConfigChangeEvent::RemoveRoot { index } => (format!("Remove root {}", index + 1), false),
+        ConfigChangeEvent::DuplicateRoot { index } => {
+            (format!("Duplicate root {}", index + 1), false)
+        }
+        ConfigChangeEvent::MoveRoot { from, to } => {
+            (format!("Move root {} to {}", from + 1, to + 1), false)
+        }
+        ConfigChangeEvent::RootPosition { index, .. } => (format!("Move root {}", index + 1), true),
+        ConfigChangeEvent::RootMultiplicity { index, .. } => {
+            (format!("Root {} multiplicity", index + 1), true)
+        }
+        ConfigChangeEvent::Coefficients(_) => ("Coefficients".into(), true),
+        ConfigChangeEvent::Function(_) => ("Function".into(), false),
+        ConfigChangeEvent::FindFunctionRoots => ("Find roots".into(), false),
+        ConfigChangeEvent::RootColor { index, .. } => (format!("Root {} color", index + 1), true),
+        // Pinch zooms move the camera too, so these share one label.
+        ConfigChangeEvent::CameraPosition(_) | ConfigChangeEvent::CameraZoom(_) => {
+            ("Camera".into(), true)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Coloring, Method};
+
+    /// Records and applies `event`, like the app does.
+    fn change(history: &mut History, config: &mut Config, event: ConfigChangeEvent) {
+        history.record(config, &event);
+        config.apply(&event);
+    }
+
+    #[test]
+    fn continuous_changes_are_coalesced() {
+        let mut history = History::default();
+        let mut config = Config::default();
+        let start = config.num_iterations;
+        for n in 1..=3 {
+            change(
+                &mut history,
+                &mut config,
+                ConfigChangeEvent::NumIterations(start + n),
+            );
+        }
+        assert_eq!(history.labels().collect::<Vec<_>>(), ["Iterations"]);
+
+        let undone = history.undo(&config).unwrap();
+        assert_eq!(undone.num_iterations, start);
+        assert!(!history.can_undo());
+        let redone = history.redo(&undone).unwrap();
+        assert_eq!(redone.num_iterations, start + 3);
+    }
+
+    #[test]
+    fn discrete_changes_are_separate() {
+        let mut history = History::default();
+        let mut config = Config::default();
+        change(
+            &mut history,
+            &mut config,
+            ConfigChangeEvent::Method(Method::Halley),
+        );
+        change(
+            &mut history,
+            &mut config,
+            ConfigChangeEvent::Method(Method::Newton),
+        );
+        // Different labels are never merged, even if both are continuous.
+        change(
+            &mut history,
+            &mut config,
+            ConfigChangeEvent::NumIterations(50),
+        );
+        change(&mut history, &mut config, ConfigChangeEvent::Epsilon(1e-3));
+        assert_eq!(
+            history.labels().collect::<Vec<_>>(),
+            ["Method", "Method", "Iterations", "Epsilon"]
+        );
+    }
+
+    #[test]
+    fn a_new_change_clears_redo() {
+        let mut history = History::default();
+        let mut config = Config::default();
+        change(&mut history, &mut config, ConfigChangeEvent::AddRoot);
+        change(
+            &mut history,
+            &mut config,
+            ConfigChangeEvent::Method(Method::Halley),
+        );
+        config = history.undo(&config).unwrap();
+        assert!(history.can_redo());
+
+        change(
+            &mut history,
+            &mut config,
+            ConfigChangeEvent::Coloring(Coloring::IterationCount),
+        );
+        assert!(!history.can_redo());
+        assert_eq!(
+            history.labels().collect::<Vec<_>>(),
+            ["Add root", "Coloring"]
+        );
+    }
+
+    #[test]
+    fn go_to_undoes_and_redoes_several_changes() {
+        let mut history = History::default();
+        let mut config = Config::default();
+        let num_roots = config.roots.len();
+        change(&mut history, &mut config, ConfigChangeEvent::AddRoot);
+        change(
+            &mut history,
+            &mut config,
+            ConfigChangeEvent::Method(Method::Halley),
+        );
+        change(
+            &mut history,
+            &mut config,
+            ConfigChangeEvent::Coloring(Coloring::IterationCount),
+        );
+
+        let config = history.go_to(&config, 1).unwrap();
+        assert_eq!(history.position(), 1);
+        assert_eq!(history.labels().count(), 3);
+        assert_eq!(config.roots.len(), num_roots + 1);
+        assert_eq!(config.method, Method::Newton);
+        assert_eq!(config.coloring, Coloring::Flat);
+
+        assert!(history.go_to(&config, 1).is_none());
+
+        let config = history.go_to(&config, 3).unwrap();
+        assert_eq!(history.position(), 3);
+        assert_eq!(config.method, Method::Halley);
+        assert_eq!(config.coloring, Coloring::IterationCount);
+
+        let config = history.go_to(&config, 0).unwrap();
+        assert_eq!(config.roots.len(), num_roots);
+        assert!(!history.can_undo());
+    }
+}
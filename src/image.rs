@@ -0,0 +1,6 @@
+/// An 8-bit RGBA image, stored row-major from top to bottom.
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
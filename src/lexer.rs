@@ -0,0 +1,90 @@
+//! The character-level part of the expression parsers in [`crate::poly`] and
+//! [`crate::expr`].
+//!
+//! Both parsers share the grammar of sums and products:
+//!
+//! ```text
+//! expression = ["+" | "-"] term {("+" | "-") term}
+//! term       = factor {["*" | "/"] factor}
+//! ```
+//!
+//! where a factor that directly follows another one is multiplied with it, as
+//! in `3z^2` or `2z sin(z)`. They differ in what a factor can be.
+
+/// Reads an expression one character or number at a time, skipping
+/// whitespace in between.
+pub(crate) struct Lexer {
+    chars: Vec<char>,
+    /// The index of the next character. Errors report it one-based.
+    pub pos: usize,
+}
+
+impl Lexer {
+    pub fn new(source: &str) -> Self {
+        Self {
+            chars: source.chars().collect(),
+            pos: 0,
+        }
+    }
+
+    /// The next character that is not whitespace, without consuming it.
+    pub fn peek(&mut self) -> Option<char> {
+        while self.chars.get(self.pos).is_some_and(|c| c.is_whitespace()) {
+            self.pos += 1;
+        }
+        self.chars.get(self.pos).copied()
+    }
+
+    /// Consumes the next character if it is `c`.
+    pub fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Fails unless the whole expression has been consumed.
+    pub fn finish(&mut self) -> anyhow::Result<()> {
+        if let Some(c) = self.peek() {
+            anyhow::bail!("unexpected {:?} at position {}", c, self.pos + 1);
+        }
+        Ok(())
+    }
+
+    /// The letters from the current position up to the next non-letter.
+    pub fn letters(&self) -> String {
+        self.chars[self.pos..]
+            .iter()
+            .take_while(|c| c.is_ascii_alphabetic())
+            .collect()
+    }
+
+    /// A decimal number, with an optional exponent like `1.5e-3`.
+    pub fn number(&mut self) -> anyhow::Result<f64> {
+        self.peek();
+        let start = self.pos;
+        let is_digit =
+            |chars: &[char], pos: usize| chars.get(pos).is_some_and(char::is_ascii_digit);
+        while is_digit(&self.chars, self.pos) || self.chars.get(self.pos) == Some(&'.') {
+            self.pos += 1;
+        }
+        // Only an `e` that is followed by digits is an exponent.
+        if self.chars.get(self.pos) == Some(&'e') {
+            let mut end = self.pos + 1;
+            if matches!(self.chars.get(end), Some('+') | Some('-')) {
+                end += 1;
+            }
+            if is_digit(&self.chars, end) {
+                self.pos = end;
+                while is_digit(&self.chars, self.pos) {
+                    self.pos += 1;
+                }
+            }
+        }
+        let text: String = self.chars[start..self.pos].iter().collect();
+        text.parse()
+            .map_err(|_| anyhow::anyhow!("invalid number {:?} at position {}", text, start + 1))
+    }
+}
@@ -0,0 +1,16 @@
+//! GPU-independent parts of the Newton fractal renderer.
+//!
+//! Everything in here can be used without a window or graphics adapter, which
+//! makes it suitable for headless rendering and testing.
+
+pub mod animation;
+pub mod attractor;
+pub mod config;
+pub mod cpu;
+pub mod deep;
+pub mod expr;
+pub mod history;
+pub mod image;
+mod lexer;
+pub mod poly;
+pub mod precise;
@@ -1,32 +1,61 @@
-use std::{sync::Arc, time::Instant};
+use std::{env, path::PathBuf, sync::Arc, time::Instant};
 
-use anyhow::Context;
+use animation::{Timeline, TimelineEvent};
+use anyhow::{anyhow, bail, ensure, Context};
+use camera::CameraController;
 use config::{Config, ConfigChangeEvent};
 use fractal::FractalRenderer;
+use glam::Vec2;
+use history::History;
+use newton_fractal::{
+    animation, attractor, config, cpu, deep, expr, history, image, poly, precise,
+};
+use overlay::RootHandles;
 use pollster::block_on;
-use ui::UiRenderer;
+use ui::{AppState, UiRenderer};
 use winit::{
-    event::WindowEvent,
+    event::{ElementState, KeyboardInput, ModifiersState, VirtualKeyCode, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
     window::{Window, WindowBuilder},
 };
 
-pub mod config;
+pub mod camera;
+pub mod export;
 pub mod fractal;
+pub mod overlay;
 pub mod ui;
 
 pub type Event<'a> = winit::event::Event<'a, AppEvent>;
 
 pub enum AppEvent {
     ConfigChange(ConfigChangeEvent),
+    SavePreset(PathBuf),
+    LoadPreset(PathBuf),
+    Undo,
+    Redo,
+    /// Undoes or redoes changes until the given number of them is applied,
+    /// see [`History::labels`].
+    GoToHistory(usize),
+    Timeline(TimelineEvent),
+    /// Starts or pauses the animation. Starting at the end starts over.
+    PlayAnimation(bool),
+    /// Jumps to the given time of the animation, in seconds.
+    SeekAnimation(f64),
+    SaveAnimation(PathBuf),
+    LoadAnimation(PathBuf),
+    ExportPng {
+        path: PathBuf,
+        width: u32,
+        height: u32,
+    },
 }
 
 pub type GraphicsContext = Arc<GraphicsContextInner>;
 
+/// The device that everything is rendered with. It doesn't depend on a
+/// window, so it can be used for offscreen rendering too.
 pub struct GraphicsContextInner {
-    pub window: Window,
     pub instance: wgpu::Instance,
-    pub surface: wgpu::Surface,
     pub adapter: wgpu::Adapter,
     pub device: wgpu::Device,
     pub queue: wgpu::Queue,
@@ -34,7 +63,9 @@ pub struct GraphicsContextInner {
 }
 
 impl GraphicsContextInner {
-    async fn new(window: Window) -> anyhow::Result<Self> {
+    /// Creates a context that can present to `window`, along with the surface
+    /// of the window.
+    async fn new(window: Window) -> anyhow::Result<(Self, Surface)> {
         let instance = wgpu::Instance::new(wgpu::Backends::PRIMARY);
         let surface = unsafe { instance.create_surface(&window) };
         let adapter = instance
@@ -44,8 +75,40 @@ impl GraphicsContextInner {
             })
             .await
             .context("failed to create adapter")?;
+        let gfx = Self::with_adapter(instance, adapter).await?;
+        let surface = Arc::new(SurfaceInner { window, surface });
+        surface.reconfigure(&gfx);
+        Ok((gfx, surface))
+    }
+
+    /// Creates a context without a window. This uses a software adapter, like
+    /// lavapipe, so that it works on machines without a GPU.
+    async fn headless() -> anyhow::Result<Self> {
+        let instance = wgpu::Instance::new(wgpu::Backends::PRIMARY);
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                force_fallback_adapter: true,
+                ..Default::default()
+            })
+            .await
+            .context("failed to create software adapter")?;
+        Self::with_adapter(instance, adapter).await
+    }
+
+    async fn with_adapter(
+        instance: wgpu::Instance,
+        adapter: wgpu::Adapter,
+    ) -> anyhow::Result<Self> {
+        // Double precision is emulated if the adapter doesn't support it.
+        let features = adapter.features() & wgpu::Features::SHADER_FLOAT64;
         let (device, queue) = adapter
-            .request_device(&Default::default(), None)
+            .request_device(
+                &wgpu::DeviceDescriptor {
+                    features,
+                    ..Default::default()
+                },
+                None,
+            )
             .await
             .context("failed to create device")?;
         // let render_format = surface
@@ -53,28 +116,40 @@ impl GraphicsContextInner {
         //     .context("failed to select a render format")?;
         let render_format = wgpu::TextureFormat::Rgba8Unorm;
 
-        let out = Self {
-            window,
+        Ok(Self {
             instance,
-            surface,
             adapter,
             device,
             queue,
             render_format,
-        };
-        out.reconfigure();
-        Ok(out)
+        })
     }
+}
 
-    fn reconfigure(&self) {
+pub type Surface = Arc<SurfaceInner>;
+
+/// The window and the surface that frames are presented to.
+pub struct SurfaceInner {
+    pub window: Window,
+    pub surface: wgpu::Surface,
+}
+
+impl SurfaceInner {
+    /// Size of the surface in physical pixels.
+    pub fn size(&self) -> Vec2 {
         let size = self.window.inner_size();
+        Vec2::new(size.width as f32, size.height as f32)
+    }
+
+    fn reconfigure(&self, gfx: &GraphicsContextInner) {
+        let size = self.size();
         self.surface.configure(
-            &self.device,
+            &gfx.device,
             &wgpu::SurfaceConfiguration {
                 usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-                format: self.render_format,
-                width: size.width,
-                height: size.height,
+                format: gfx.render_format,
+                width: size.x as u32,
+                height: size.y as u32,
                 present_mode: wgpu::PresentMode::Fifo,
             },
         );
@@ -83,23 +158,46 @@ impl GraphicsContextInner {
 
 pub struct App {
     gfx: GraphicsContext,
+    surface: Surface,
     fractal_renderer: FractalRenderer,
     ui_renderer: UiRenderer,
+    camera_controller: CameraController,
+    root_handles: RootHandles,
     last_frame: Instant,
     config: Config,
+    history: History,
+    modifiers: ModifiersState,
+    timeline: Timeline,
+    /// The current time of the animation in seconds, and whether it is
+    /// playing.
+    animation_time: f64,
+    playing: bool,
 }
 
 impl App {
-    pub async fn new(window: Window, event_loop: &EventLoop<AppEvent>) -> anyhow::Result<Self> {
-        let gfx = Arc::new(GraphicsContextInner::new(window).await?);
+    pub async fn new(
+        window: Window,
+        event_loop: &EventLoop<AppEvent>,
+        config: Config,
+    ) -> anyhow::Result<Self> {
+        let (gfx, surface) = GraphicsContextInner::new(window).await?;
+        let gfx = Arc::new(gfx);
         let fractal_renderer = FractalRenderer::new(&gfx);
-        let ui_renderer = UiRenderer::new(&gfx, event_loop);
+        let ui_renderer = UiRenderer::new(&gfx, &surface, event_loop);
         Ok(Self {
             gfx,
+            surface,
             fractal_renderer,
             ui_renderer,
+            camera_controller: Default::default(),
+            root_handles: Default::default(),
             last_frame: Instant::now(),
-            config: Default::default(),
+            config,
+            history: Default::default(),
+            modifiers: Default::default(),
+            timeline: Default::default(),
+            animation_time: 0.0,
+            playing: false,
         })
     }
 
@@ -107,38 +205,178 @@ impl App {
         self.ui_renderer.handle_event(event);
         match event {
             Event::MainEventsCleared => {
-                self.gfx.window.request_redraw();
+                self.surface.window.request_redraw();
             }
             Event::RedrawRequested(..) => {
                 let now = Instant::now();
                 let dt = now - self.last_frame;
                 self.last_frame = now;
                 self.ui_renderer.update(dt);
+                if self.playing {
+                    self.seek_animation(self.animation_time + dt.as_secs_f64());
+                }
 
                 self.redraw().unwrap();
             }
-            Event::WindowEvent { event, .. } => match event {
-                WindowEvent::CloseRequested => {
-                    *control_flow = ControlFlow::Exit;
+            Event::WindowEvent { event, .. } => {
+                match event {
+                    WindowEvent::CloseRequested => {
+                        *control_flow = ControlFlow::Exit;
+                    }
+                    WindowEvent::Resized(..) | WindowEvent::ScaleFactorChanged { .. } => {
+                        self.surface.reconfigure(&self.gfx);
+                    }
+                    WindowEvent::ModifiersChanged(modifiers) => {
+                        self.modifiers = *modifiers;
+                    }
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::Z),
+                                ..
+                            },
+                        ..
+                    } if self.modifiers.ctrl() && !self.ui_renderer.has_keyboard_focus() => {
+                        if self.modifiers.shift() {
+                            self.redo();
+                        } else {
+                            self.undo();
+                        }
+                    }
+                    _ => {}
                 }
-                WindowEvent::Resized(..) | WindowEvent::ScaleFactorChanged { .. } => {
-                    self.gfx.reconfigure();
+
+                let size = self.surface.size();
+                let ui_has_mouse = self.ui_renderer.has_mouse_focus();
+                let root_change = self.root_handles.handle_event(
+                    event,
+                    &self.config,
+                    size,
+                    self.surface.window.scale_factor() as f32,
+                    ui_has_mouse,
+                );
+                if let Some(config_change) = &root_change {
+                    self.apply_config_change(config_change);
                 }
-                _ => {}
-            },
+                // Dragging a root takes priority over panning the camera.
+                let camera_changes = self.camera_controller.handle_event(
+                    event,
+                    &self.config,
+                    size,
+                    ui_has_mouse || self.root_handles.is_dragging(),
+                );
+                for config_change in &camera_changes {
+                    self.apply_config_change(config_change);
+                }
+            }
             Event::UserEvent(AppEvent::ConfigChange(config_change)) => {
-                self.config.apply(config_change);
+                self.apply_config_change(config_change);
+            }
+            Event::UserEvent(AppEvent::SavePreset(path)) => {
+                if let Err(err) = self.config.save(path) {
+                    log::error!("{:#}", err);
+                }
+            }
+            Event::UserEvent(AppEvent::LoadPreset(path)) => match Config::load(path) {
+                Ok(config) => {
+                    self.history.push(&self.config, "Load preset");
+                    self.config = config;
+                }
+                Err(err) => {
+                    log::error!("{:#}", err);
+                }
+            },
+            Event::UserEvent(AppEvent::Undo) => self.undo(),
+            Event::UserEvent(AppEvent::Redo) => self.redo(),
+            Event::UserEvent(AppEvent::GoToHistory(position)) => {
+                if let Some(config) = self.history.go_to(&self.config, *position) {
+                    self.config = config;
+                }
+            }
+            Event::UserEvent(AppEvent::Timeline(event)) => {
+                self.timeline.apply(event, &self.config);
+            }
+            Event::UserEvent(AppEvent::PlayAnimation(playing)) => {
+                if *playing && self.animation_time >= self.timeline.duration() {
+                    self.animation_time = 0.0;
+                }
+                self.playing = *playing;
+            }
+            Event::UserEvent(AppEvent::SeekAnimation(time)) => {
+                self.seek_animation(*time);
+            }
+            Event::UserEvent(AppEvent::SaveAnimation(path)) => {
+                if let Err(err) = self.timeline.save(path) {
+                    log::error!("{:#}", err);
+                }
+            }
+            Event::UserEvent(AppEvent::LoadAnimation(path)) => match Timeline::load(path) {
+                Ok(timeline) => {
+                    self.timeline = timeline;
+                    self.playing = false;
+                    self.seek_animation(0.0);
+                }
+                Err(err) => {
+                    log::error!("{:#}", err);
+                }
+            },
+            Event::UserEvent(AppEvent::ExportPng {
+                path,
+                width,
+                height,
+            }) => {
+                let result = export::export_png(
+                    Some(&mut self.fractal_renderer),
+                    &self.config,
+                    *width,
+                    *height,
+                    path,
+                );
+                if let Err(err) = result {
+                    log::error!("{:#}", err);
+                }
             }
             _ => {}
         }
     }
 
+    fn apply_config_change(&mut self, config_change: &ConfigChangeEvent) {
+        self.history.record(&self.config, config_change);
+        self.config.apply(config_change);
+    }
+
+    /// Moves the animation to `time`, replacing the config with the one on the
+    /// timeline. Playback stops at the end.
+    fn seek_animation(&mut self, time: f64) {
+        let duration = self.timeline.duration();
+        if time >= duration {
+            self.playing = false;
+        }
+        self.animation_time = time.clamp(0.0, duration);
+        if let Some(config) = self.timeline.sample(self.animation_time) {
+            self.config = config;
+        }
+    }
+
+    fn undo(&mut self) {
+        if let Some(config) = self.history.undo(&self.config) {
+            self.config = config;
+        }
+    }
+
+    fn redo(&mut self) {
+        if let Some(config) = self.history.redo(&self.config) {
+            self.config = config;
+        }
+    }
+
     fn redraw(&mut self) -> anyhow::Result<()> {
         let frame = loop {
-            match self.gfx.surface.get_current_texture() {
+            match self.surface.surface.get_current_texture() {
                 Ok(frame) => break frame,
                 Err(wgpu::SurfaceError::Lost) => {
-                    self.gfx.reconfigure();
+                    self.surface.reconfigure(&self.gfx);
                 }
                 Err(wgpu::SurfaceError::Timeout) | Err(wgpu::SurfaceError::Outdated) => {
                     return Ok(());
@@ -151,10 +389,26 @@ impl App {
 
         let frame_view = frame.texture.create_view(&Default::default());
         let mut encoder = self.gfx.device.create_command_encoder(&Default::default());
-        self.fractal_renderer
-            .draw(&mut encoder, &frame_view, &self.config);
-        self.ui_renderer
-            .draw(&mut encoder, &frame_view, &self.config)?;
+        let precision = self.fractal_renderer.draw(
+            &mut encoder,
+            &frame_view,
+            &self.config,
+            self.surface.size(),
+        );
+        self.ui_renderer.draw(
+            &mut encoder,
+            &frame_view,
+            &self.config,
+            AppState {
+                root_handles: &self.root_handles,
+                precision,
+                attractors: self.fractal_renderer.attractors(),
+                history: &self.history,
+                timeline: &self.timeline,
+                animation_time: self.animation_time,
+                playing: self.playing,
+            },
+        )?;
         self.gfx.queue.submit([encoder.finish()]);
         frame.present();
 
@@ -162,15 +416,141 @@ impl App {
     }
 }
 
+struct Args {
+    config: Option<PathBuf>,
+    export: Option<PathBuf>,
+    size: (u32, u32),
+    /// The animation to render with `render-anim`.
+    animation: Option<PathBuf>,
+    /// The directory that the frames of the animation are written to.
+    out: Option<PathBuf>,
+    fps: f64,
+    /// A shell command that the frames of the animation are piped into.
+    encoder: Option<String>,
+}
+
+impl Default for Args {
+    fn default() -> Self {
+        Self {
+            config: None,
+            export: None,
+            size: (1920, 1080),
+            animation: None,
+            out: None,
+            fps: 30.0,
+            encoder: None,
+        }
+    }
+}
+
+impl Args {
+    fn parse() -> anyhow::Result<Self> {
+        let mut args = Self::default();
+        let mut iter = env::args_os().skip(1);
+        while let Some(arg) = iter.next() {
+            match arg.to_str() {
+                Some("--config") => {
+                    let path = iter.next().context("--config requires a path")?;
+                    args.config = Some(path.into());
+                }
+                Some("--export") => {
+                    let path = iter.next().context("--export requires a path")?;
+                    args.export = Some(path.into());
+                }
+                Some("render-anim") => {
+                    let path = iter.next().context("render-anim requires a path")?;
+                    args.animation = Some(path.into());
+                }
+                Some("--out") => {
+                    let path = iter.next().context("--out requires a path")?;
+                    args.out = Some(path.into());
+                }
+                Some("--fps") => {
+                    let fps = iter.next().context("--fps requires a frame rate")?;
+                    args.fps = fps
+                        .to_str()
+                        .and_then(|fps| fps.parse().ok())
+                        .with_context(|| format!("invalid frame rate {:?}", fps))?;
+                }
+                Some("--encoder") => {
+                    let command = iter.next().context("--encoder requires a command")?;
+                    let command = command
+                        .into_string()
+                        .map_err(|command| anyhow!("invalid command {:?}", command))?;
+                    args.encoder = Some(command);
+                }
+                Some("--size") => {
+                    let size = iter.next().context("--size requires a size")?;
+                    args.size = size
+                        .to_str()
+                        .and_then(parse_size)
+                        .with_context(|| format!("invalid size {:?}, expected WxH", size))?;
+                }
+                _ => bail!("unexpected argument {:?}", arg),
+            }
+        }
+        Ok(args)
+    }
+}
+
+fn parse_size(s: &str) -> Option<(u32, u32)> {
+    let (width, height) = s.split_once('x')?;
+    Some((width.parse().ok()?, height.parse().ok()?))
+}
+
+/// Renders the animation or the config given in `args` without opening a
+/// window.
+fn run_headless(args: &Args, config: &Config) -> anyhow::Result<()> {
+    ensure!(
+        args.animation.is_none() || args.out.is_some() || args.encoder.is_some(),
+        "render-anim requires --out or --encoder"
+    );
+    let mut renderer = match block_on(GraphicsContextInner::headless()) {
+        Ok(gfx) => Some(FractalRenderer::new(&Arc::new(gfx))),
+        Err(err) => {
+            log::warn!("{:#}, rendering on the CPU instead", err);
+            None
+        }
+    };
+
+    let (width, height) = args.size;
+    if let Some(path) = &args.animation {
+        let timeline = Timeline::load(path)?;
+        export::export_animation(
+            renderer.as_mut(),
+            &timeline,
+            args.fps,
+            width,
+            height,
+            args.out.as_deref(),
+            args.encoder.as_deref(),
+        )
+    } else if let Some(path) = &args.export {
+        export::export_png(renderer.as_mut(), config, width, height, path)
+    } else {
+        Ok(())
+    }
+}
+
 fn main() -> anyhow::Result<()> {
     env_logger::init();
+    let args = Args::parse()?;
+    let config = match &args.config {
+        Some(path) => Config::load(path)?,
+        None => Config::default(),
+    };
+
+    if args.animation.is_some() || args.export.is_some() {
+        return run_headless(&args, &config);
+    }
+
     let event_loop = EventLoop::with_user_event();
     let window = WindowBuilder::new()
         .with_title("Newton Fractal")
         .build(&event_loop)
         .context("failed to create window")?;
 
-    let mut app = block_on(App::new(window, &event_loop))?;
+    let mut app = block_on(App::new(window, &event_loop, config))?;
 
     event_loop.run(move |event, _, control_flow| {
         app.handle_event(&event, control_flow);
@@ -0,0 +1,128 @@
+use glam::Vec2;
+use imgui::Ui;
+use winit::event::{ElementState, MouseButton, WindowEvent};
+
+use crate::config::{Config, ConfigChangeEvent};
+
+/// Radius of a root handle, in logical pixels.
+const HANDLE_RADIUS: f32 = 6.0;
+
+/// Handles drawn over the fractal at the position of each root, which can be
+/// dragged with the mouse to move the root.
+#[derive(Default)]
+pub struct RootHandles {
+    /// Cursor position in physical pixels.
+    cursor: Option<Vec2>,
+    hovered: Option<usize>,
+    drag: Option<Drag>,
+}
+
+struct Drag {
+    index: usize,
+    /// Offset from the cursor to the center of the handle when the drag
+    /// started, in physical pixels. Keeps the root from jumping to the cursor.
+    offset: Vec2,
+}
+
+impl RootHandles {
+    /// Whether a root is currently being dragged.
+    pub fn is_dragging(&self) -> bool {
+        self.drag.is_some()
+    }
+
+    /// Handles a window event, returning the resulting changes to the roots.
+    ///
+    /// `window_size` is the size of the fractal view in physical pixels. New
+    /// drags are ignored while `ui_has_mouse` is set.
+    pub fn handle_event(
+        &mut self,
+        event: &WindowEvent,
+        config: &Config,
+        window_size: Vec2,
+        scale_factor: f32,
+        ui_has_mouse: bool,
+    ) -> Option<ConfigChangeEvent> {
+        match event {
+            WindowEvent::CursorMoved { position, .. } => {
+                let cursor = Vec2::new(position.x as f32, position.y as f32);
+                self.cursor = Some(cursor);
+                self.hovered = hit_test(config, cursor, window_size, scale_factor);
+                if let Some(drag) = &self.drag {
+                    let region = config.camera.region(window_size);
+                    return Some(ConfigChangeEvent::RootPosition {
+                        index: drag.index,
+                        position: region
+                            .pixel_to_point(cursor + drag.offset, window_size)
+                            .as_vec2(),
+                    });
+                }
+            }
+            WindowEvent::CursorLeft { .. } => {
+                self.cursor = None;
+                self.hovered = None;
+            }
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Left,
+                ..
+            } => {
+                if let (false, Some(index), Some(cursor)) =
+                    (ui_has_mouse, self.hovered, self.cursor)
+                {
+                    if let Some(root) = config.roots.get(index) {
+                        let region = config.camera.region(window_size);
+                        let center = region.point_to_pixel(root.position.as_dvec2(), window_size);
+                        self.drag = Some(Drag {
+                            index,
+                            offset: center - cursor,
+                        });
+                    }
+                }
+            }
+            WindowEvent::MouseInput {
+                state: ElementState::Released,
+                button: MouseButton::Left,
+                ..
+            } => {
+                self.drag = None;
+            }
+            _ => {}
+        }
+        None
+    }
+
+    /// Draws the handles behind all UI windows.
+    pub fn draw(&self, ui: &Ui, config: &Config) {
+        let display_size = Vec2::from(ui.io().display_size);
+        let region = config.camera.region(display_size);
+        let draw_list = ui.get_background_draw_list();
+        for (i, root) in config.roots.iter().enumerate() {
+            let center = region.point_to_pixel(root.position.as_dvec2(), display_size);
+            let active = self.hovered == Some(i) || self.drag.as_ref().map(|d| d.index) == Some(i);
+            let outline = if active {
+                [1.0, 1.0, 0.0, 1.0]
+            } else {
+                [1.0, 1.0, 1.0, 1.0]
+            };
+            draw_list
+                .add_circle(center.into(), HANDLE_RADIUS, root.color.to_array())
+                .filled(true)
+                .build();
+            draw_list
+                .add_circle(center.into(), HANDLE_RADIUS, outline)
+                .thickness(2.0)
+                .build();
+        }
+    }
+}
+
+/// Returns the index of the topmost root handle under `cursor`, if any.
+fn hit_test(config: &Config, cursor: Vec2, window_size: Vec2, scale_factor: f32) -> Option<usize> {
+    let region = config.camera.region(window_size);
+    let radius = HANDLE_RADIUS * scale_factor;
+    // Later handles are drawn on top, so they take priority.
+    config.roots.iter().enumerate().rev().find_map(|(i, root)| {
+        let center = region.point_to_pixel(root.position.as_dvec2(), window_size);
+        (center.distance(cursor) <= radius).then_some(i)
+    })
+}
@@ -0,0 +1,590 @@
+//! Polynomial helpers shared by the GPU and CPU renderers, and the conversions
+//! between the representations in the config.
+
+use num_complex::Complex64;
+
+use crate::lexer::Lexer;
+
+/// Expands the factored form `(z - r1) * (z - r2) * ...` into coefficients in
+/// ascending-power form.
+///
+/// The element at index `i` of the result is the coefficient of `z^i`. The
+/// result always has `roots.len() + 1` elements, with the leading coefficient
+/// equal to 1.
+pub fn expand_roots<I>(roots: I) -> Vec<Complex64>
+where
+    I: IntoIterator<Item = Complex64>,
+{
+    let mut p = vec![Complex64::new(1.0, 0.0)];
+    for root in roots {
+        // Multiply p by (z - root): shift forward by one power, then subtract
+        // root * p from the result.
+        p.insert(0, Complex64::new(0.0, 0.0));
+        for i in 0..p.len() - 1 {
+            let term = p[i + 1] * root;
+            p[i] -= term;
+        }
+    }
+    p
+}
+
+/// The highest degree that [`parse`] accepts.
+pub const MAX_DEGREE: usize = 256;
+
+/// Roots that are further apart than this, relative to their magnitude, are
+/// never grouped by [`group_roots`].
+const CLUSTER_RADIUS: f64 = 0.1;
+
+/// How many times the rounding error bound the derivatives may be off from
+/// zero at a multiple root, for [`group_roots`].
+const MULTIPLE_ROOT_TOLERANCE: f64 = 64.0;
+
+/// Iteration limit of [`find_roots`]. Simple roots converge within a few
+/// dozen iterations, multiple roots only linearly.
+const MAX_ROOT_ITERATIONS: usize = 500;
+
+/// Removes the zero coefficients of the highest powers, so that the last
+/// element is the leading coefficient.
+pub fn trim(coefficients: &mut Vec<Complex64>) {
+    while coefficients.last() == Some(&Complex64::new(0.0, 0.0)) {
+        coefficients.pop();
+    }
+}
+
+/// Finds the roots of the polynomial with the ascending `coefficients`, using
+/// the Aberth-Ehrlich method.
+///
+/// `guesses` are starting points for the first roots, e.g. the roots before a
+/// coefficient was changed. Each root is then found near its guess, so the
+/// order stays the same. The remaining roots start on a circle that encloses
+/// all of them.
+///
+/// The leading coefficient must not be zero.
+pub fn find_roots(coefficients: &[Complex64], guesses: &[Complex64]) -> Vec<Complex64> {
+    let degree = coefficients.len().saturating_sub(1);
+    if degree == 0 {
+        return Vec::new();
+    }
+    let leading = coefficients[degree];
+    // Cauchy's bound on the magnitude of the roots.
+    let radius = 1.0
+        + coefficients[..degree]
+            .iter()
+            .map(|&c| (c / leading).norm())
+            .fold(0.0, f64::max);
+
+    let mut roots: Vec<Complex64> = guesses.iter().copied().take(degree).collect();
+    for k in roots.len()..degree {
+        // The offset breaks the symmetry of polynomials like `z^n - 1`.
+        let angle = std::f64::consts::TAU * k as f64 / degree as f64 + 0.4;
+        roots.push(Complex64::from_polar(radius, angle));
+    }
+    // Equal guesses would never separate.
+    for k in 1..degree {
+        while roots[..k].contains(&roots[k]) {
+            roots[k] += Complex64::from_polar(1e-6 * radius, k as f64);
+        }
+    }
+
+    for _ in 0..MAX_ROOT_ITERATIONS {
+        let mut converged = true;
+        for k in 0..degree {
+            let z = roots[k];
+            let (value, derivative) = evaluate(coefficients, z);
+            if value == Complex64::new(0.0, 0.0) {
+                continue;
+            }
+            let ratio = value / derivative;
+            let repulsion: Complex64 = roots
+                .iter()
+                .enumerate()
+                .filter(|&(j, _)| j != k)
+                .map(|(_, &other)| 1.0 / (z - other))
+                .sum();
+            let step = ratio / (1.0 - ratio * repulsion);
+            if !step.is_finite() {
+                continue;
+            }
+            roots[k] = z - step;
+            if step.norm() > 4.0 * f64::EPSILON * z.norm().max(1.0) {
+                converged = false;
+            }
+        }
+        if converged {
+            break;
+        }
+    }
+    roots
+}
+
+/// Groups the `roots` of the polynomial with the ascending `coefficients`
+/// that are the same multiple root, as the position and the multiplicity.
+///
+/// A root of multiplicity `m` can only be found to about `1/m` of the digits,
+/// so [`find_roots`] returns a cluster of `m` roots around it. Clusters of
+/// nearby roots are accepted as a multiple root if the polynomial and its
+/// first `m - 1` derivatives vanish at their center, up to rounding errors.
+pub fn group_roots(coefficients: &[Complex64], roots: &[Complex64]) -> Vec<(Complex64, usize)> {
+    let mut remaining = roots.to_vec();
+    let mut groups = Vec::new();
+    while !remaining.is_empty() {
+        let first = remaining.remove(0);
+        let mut neighbors: Vec<(usize, f64)> = remaining
+            .iter()
+            .enumerate()
+            .map(|(i, &root)| (i, (root - first).norm()))
+            .filter(|&(_, distance)| distance < CLUSTER_RADIUS * first.norm().max(1.0))
+            .collect();
+        neighbors.sort_by(|a, b| a.1.total_cmp(&b.1));
+
+        // The largest cluster around `first` that is a multiple root.
+        let mut group = (first, 1);
+        let mut members: Vec<usize> = Vec::new();
+        for size in (2..=neighbors.len() + 1).rev() {
+            let cluster = &neighbors[..size - 1];
+            let sum: Complex64 = first
+                + cluster
+                    .iter()
+                    .map(|&(i, _)| remaining[i])
+                    .sum::<Complex64>();
+            if let Some(root) = multiple_root(coefficients, sum / size as f64, size) {
+                group = (root, size);
+                members = cluster.iter().map(|&(i, _)| i).collect();
+                break;
+            }
+        }
+        members.sort_unstable();
+        for &i in members.iter().rev() {
+            remaining.remove(i);
+        }
+        groups.push(group);
+    }
+    groups
+}
+
+/// Checks whether there is a root of the given `multiplicity` near `z`, and
+/// returns its position.
+fn multiple_root(
+    coefficients: &[Complex64],
+    z: Complex64,
+    multiplicity: usize,
+) -> Option<Complex64> {
+    // The root is a simple root of the derivative of order `multiplicity - 1`,
+    // so Newton's method on that derivative finds it precisely.
+    let mut z = z;
+    for _ in 0..3 {
+        let taylor = taylor_with_bounds(coefficients, z, multiplicity).0;
+        let step = taylor[multiplicity - 1] / (multiplicity as f64 * taylor[multiplicity]);
+        if !step.is_finite() {
+            return None;
+        }
+        z -= step;
+    }
+    let (taylor, bounds) = taylor_with_bounds(coefficients, z, multiplicity);
+    let vanishes = taylor[..multiplicity]
+        .iter()
+        .zip(&bounds)
+        .all(|(a, bound)| a.norm() <= MULTIPLE_ROOT_TOLERANCE * f64::EPSILON * bound);
+    if vanishes {
+        Some(z)
+    } else {
+        None
+    }
+}
+
+/// The normalized Taylor coefficients `p^(k)(z) / k!` up to `order`, and the
+/// same sums taken over the absolute values, which bound their rounding
+/// errors.
+fn taylor_with_bounds(
+    coefficients: &[Complex64],
+    z: Complex64,
+    order: usize,
+) -> (Vec<Complex64>, Vec<f64>) {
+    let mut taylor = vec![Complex64::new(0.0, 0.0); order + 1];
+    let mut bounds = vec![0.0; order + 1];
+    for (i, &coefficient) in coefficients.iter().rev().enumerate() {
+        for k in (1..=order.min(i)).rev() {
+            taylor[k] = taylor[k] * z + taylor[k - 1];
+            bounds[k] = bounds[k] * z.norm() + bounds[k - 1];
+        }
+        taylor[0] = taylor[0] * z + coefficient;
+        bounds[0] = bounds[0] * z.norm() + coefficient.norm();
+    }
+    (taylor, bounds)
+}
+
+/// Value and derivative of the polynomial with the ascending `coefficients` at
+/// `z`, using Horner's method.
+fn evaluate(coefficients: &[Complex64], z: Complex64) -> (Complex64, Complex64) {
+    let mut value = Complex64::new(0.0, 0.0);
+    let mut derivative = Complex64::new(0.0, 0.0);
+    for &coefficient in coefficients.iter().rev() {
+        derivative = derivative * z + value;
+        value = value * z + coefficient;
+    }
+    (value, derivative)
+}
+
+/// Parses a polynomial in `z` into coefficients in ascending-power form, with
+/// the leading coefficient last.
+///
+/// The expression can use `+`, `-`, `*`, `/` by constants, `^` with integer
+/// exponents, parentheses, and the imaginary unit `i`. Multiplication can also
+/// be written by juxtaposition, as in `z^5 - 3z^2 + 1` or `(1 + 2i)(z - 1)^2`.
+pub fn parse(s: &str) -> anyhow::Result<Vec<Complex64>> {
+    let mut parser = Parser {
+        lexer: Lexer::new(s),
+    };
+    let mut coefficients = parser.expression()?;
+    parser.lexer.finish()?;
+    trim(&mut coefficients);
+    anyhow::ensure!(!coefficients.is_empty(), "the polynomial is zero");
+    anyhow::ensure!(
+        coefficients.iter().all(|c| c.is_finite()),
+        "the coefficients are too large"
+    );
+    Ok(coefficients)
+}
+
+/// Writes the polynomial with the ascending `coefficients` as an expression
+/// that [`parse`] accepts, with the highest power first.
+pub fn format(coefficients: &[Complex64]) -> String {
+    let mut s = String::new();
+    for (power, &c) in coefficients.iter().enumerate().rev() {
+        if c == Complex64::new(0.0, 0.0) {
+            continue;
+        }
+        // Real and imaginary coefficients carry their sign into the operator.
+        let (negative, c) = if (c.im == 0.0 && c.re < 0.0) || (c.re == 0.0 && c.im < 0.0) {
+            (true, -c)
+        } else {
+            (false, c)
+        };
+        if s.is_empty() {
+            if negative {
+                s.push('-');
+            }
+        } else {
+            s.push_str(if negative { " - " } else { " + " });
+        }
+        let coefficient = if c.im == 0.0 {
+            format!("{}", c.re)
+        } else if c.re == 0.0 && c.im == 1.0 {
+            "i".to_string()
+        } else if c.re == 0.0 {
+            format!("{}i", c.im)
+        } else {
+            format!(
+                "({} {} {}i)",
+                c.re,
+                if c.im < 0.0 { '-' } else { '+' },
+                c.im.abs()
+            )
+        };
+        match power {
+            0 => s.push_str(&coefficient),
+            _ => {
+                if c != Complex64::new(1.0, 0.0) {
+                    s.push_str(&coefficient);
+                }
+                s.push('z');
+                if power > 1 {
+                    s.push_str(&format!("^{}", power));
+                }
+            }
+        }
+    }
+    if s.is_empty() {
+        s.push('0');
+    }
+    s
+}
+
+/// Recursive descent parser for [`parse`], where every value is a polynomial.
+///
+/// Sums and products follow the grammar in [`crate::lexer`].
+struct Parser {
+    lexer: Lexer,
+}
+
+impl Parser {
+    fn expression(&mut self) -> anyhow::Result<Vec<Complex64>> {
+        let negative = !self.lexer.eat('+') && self.lexer.eat('-');
+        let mut result = self.term()?;
+        if negative {
+            result = scale(&result, Complex64::new(-1.0, 0.0));
+        }
+        loop {
+            if self.lexer.eat('+') {
+                result = add(&result, &self.term()?);
+            } else if self.lexer.eat('-') {
+                result = add(&result, &scale(&self.term()?, Complex64::new(-1.0, 0.0)));
+            } else {
+                return Ok(result);
+            }
+        }
+    }
+
+    fn term(&mut self) -> anyhow::Result<Vec<Complex64>> {
+        let mut result = self.factor()?;
+        loop {
+            if self.lexer.eat('/') {
+                let pos = self.lexer.pos;
+                let mut divisor = self.factor()?;
+                trim(&mut divisor);
+                match divisor[..] {
+                    [c] => result = scale(&result, 1.0 / c),
+                    [] => anyhow::bail!("division by zero at position {}", pos + 1),
+                    _ => anyhow::bail!("can only divide by constants, at position {}", pos + 1),
+                }
+            } else if self.lexer.eat('*') || self.starts_factor() {
+                result = multiply(&result, &self.factor()?)?;
+            } else {
+                return Ok(result);
+            }
+        }
+    }
+
+    fn starts_factor(&mut self) -> bool {
+        matches!(self.lexer.peek(), Some(c) if c.is_ascii_digit() || c == '.' || c == 'z' || c == 'i' || c == '(')
+    }
+
+    /// `factor = primary ["^" integer]`
+    fn factor(&mut self) -> anyhow::Result<Vec<Complex64>> {
+        let base = self.primary()?;
+        if !self.lexer.eat('^') {
+            return Ok(base);
+        }
+        let pos = self.lexer.pos;
+        let exponent = self.lexer.number()?;
+        anyhow::ensure!(
+            exponent.fract() == 0.0 && exponent >= 0.0,
+            "the exponent at position {} is not a non-negative integer",
+            pos + 1,
+        );
+        // Even constants are limited, so that the loop below stays short.
+        anyhow::ensure!(
+            exponent <= MAX_DEGREE as f64,
+            "the exponent at position {} is larger than {}",
+            pos + 1,
+            MAX_DEGREE,
+        );
+        // Exponentiation by squaring. The base is only squared while there are
+        // higher bits left, so no intermediate power exceeds the result.
+        let mut exponent = exponent as usize;
+        let mut base = base;
+        let mut result = vec![Complex64::new(1.0, 0.0)];
+        while exponent > 0 {
+            if exponent & 1 == 1 {
+                result = multiply(&result, &base)?;
+            }
+            exponent >>= 1;
+            if exponent > 0 {
+                base = multiply(&base, &base)?;
+            }
+        }
+        Ok(result)
+    }
+
+    /// `primary = number | "z" | "i" | "(" expression ")"`
+    fn primary(&mut self) -> anyhow::Result<Vec<Complex64>> {
+        match self.lexer.peek() {
+            Some('z') => {
+                self.lexer.pos += 1;
+                Ok(vec![Complex64::new(0.0, 0.0), Complex64::new(1.0, 0.0)])
+            }
+            Some('i') => {
+                self.lexer.pos += 1;
+                Ok(vec![Complex64::new(0.0, 1.0)])
+            }
+            Some('(') => {
+                self.lexer.pos += 1;
+                let result = self.expression()?;
+                anyhow::ensure!(
+                    self.lexer.eat(')'),
+                    "missing ')' at position {}",
+                    self.lexer.pos + 1
+                );
+                Ok(result)
+            }
+            Some(c) if c.is_ascii_digit() || c == '.' => {
+                Ok(vec![Complex64::new(self.lexer.number()?, 0.0)])
+            }
+            Some(c) => anyhow::bail!("unexpected {:?} at position {}", c, self.lexer.pos + 1),
+            None => anyhow::bail!("unexpected end of the expression"),
+        }
+    }
+}
+
+fn add(a: &[Complex64], b: &[Complex64]) -> Vec<Complex64> {
+    let mut result = vec![Complex64::new(0.0, 0.0); a.len().max(b.len())];
+    for (i, &c) in a.iter().enumerate() {
+        result[i] += c;
+    }
+    for (i, &c) in b.iter().enumerate() {
+        result[i] += c;
+    }
+    result
+}
+
+fn scale(a: &[Complex64], factor: Complex64) -> Vec<Complex64> {
+    a.iter().map(|&c| c * factor).collect()
+}
+
+/// Multiplies two polynomials, failing if the product has a degree above
+/// [`MAX_DEGREE`].
+fn multiply(a: &[Complex64], b: &[Complex64]) -> anyhow::Result<Vec<Complex64>> {
+    // Zero coefficients of the highest powers don't count towards the degree.
+    let degree = |p: &[Complex64]| {
+        p.iter()
+            .rposition(|&c| c != Complex64::new(0.0, 0.0))
+            .unwrap_or(0)
+    };
+    let (a, b) = (&a[..=degree(a)], &b[..=degree(b)]);
+    anyhow::ensure!(
+        a.len() + b.len() - 2 <= MAX_DEGREE,
+        "the degree is larger than {}",
+        MAX_DEGREE,
+    );
+    let mut result = vec![Complex64::new(0.0, 0.0); a.len() + b.len() - 1];
+    for (i, &x) in a.iter().enumerate() {
+        for (j, &y) in b.iter().enumerate() {
+            result[i + j] += x * y;
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn c(re: f64, im: f64) -> Complex64 {
+        Complex64::new(re, im)
+    }
+
+    fn assert_close(a: &[Complex64], b: &[Complex64]) {
+        assert_eq!(a.len(), b.len(), "{:?} != {:?}", a, b);
+        for (x, y) in a.iter().zip(b) {
+            assert!((x - y).norm() < 1e-9, "{:?} != {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn parse_expands_products_and_powers() {
+        // (z - 1)^2 (z + i) = z^3 + (i - 2)z^2 + (1 - 2i)z + i
+        let coefficients = parse("(z - 1)^2 (z + i)").unwrap();
+        assert_close(
+            &coefficients,
+            &[c(0.0, 1.0), c(1.0, -2.0), c(-2.0, 1.0), c(1.0, 0.0)],
+        );
+        assert_close(
+            &parse("z^5 - 3z^2 + 1").unwrap()[..3],
+            &[c(1.0, 0.0), c(0.0, 0.0), c(-3.0, 0.0)],
+        );
+        assert_close(&parse("2^10").unwrap(), &[c(1024.0, 0.0)]);
+    }
+
+    #[test]
+    fn parse_rejects_invalid_expressions() {
+        assert!(parse("z - z").is_err());
+        assert!(parse("z / z").is_err());
+        assert!(parse("z^0.5").is_err());
+        assert!(parse("(z + 1").is_err());
+    }
+
+    #[test]
+    fn parse_limits_the_degree() {
+        assert_eq!(parse("z^256").unwrap().len(), 257);
+        assert!(parse("z^257").is_err());
+        // Exponents of constants can't run for long either.
+        assert!(parse("2^1e18").is_err());
+        assert!(parse("(z^200)(z^200)").is_err());
+        assert!(parse("(z^200) * z^200").is_err());
+        // Cancelled terms don't count towards the degree.
+        assert_eq!(parse("(z - z + 1)^256 z").unwrap().len(), 2);
+        assert!(parse("1e300 * 1e300").is_err());
+    }
+
+    #[test]
+    fn format_round_trips_through_parse() {
+        for coefficients in [
+            vec![
+                c(1.0, 0.0),
+                c(0.0, 0.0),
+                c(-3.0, 0.0),
+                c(0.0, 0.0),
+                c(0.0, 0.0),
+                c(2.0, 0.0),
+            ],
+            vec![c(0.5, -1.5), c(0.0, 2.0), c(0.0, -1.0), c(1.0, 0.0)],
+            vec![c(-0.25, 0.0)],
+        ] {
+            let formatted = format(&coefficients);
+            assert_close(&parse(&formatted).unwrap(), &coefficients);
+        }
+        assert_eq!(
+            format(&[c(1.0, 0.0), c(0.0, 0.0), c(-3.0, 0.0), c(1.0, 0.0)]),
+            "z^3 - 3z^2 + 1"
+        );
+    }
+
+    #[test]
+    fn expand_roots_multiplies_the_factors() {
+        assert_close(
+            &expand_roots([c(1.0, 0.0), c(-1.0, 0.0)]),
+            &[c(-1.0, 0.0), c(0.0, 0.0), c(1.0, 0.0)],
+        );
+        assert_close(&expand_roots([]), &[c(1.0, 0.0)]);
+    }
+
+    #[test]
+    fn find_roots_inverts_expand_roots() {
+        let roots = [
+            c(1.0, 0.0),
+            c(-1.0, 0.0),
+            c(0.0, 1.0),
+            c(2.0, 1.0),
+            c(-0.5, -3.0),
+        ];
+        let coefficients = expand_roots(roots);
+        let found = find_roots(&coefficients, &[]);
+        assert_eq!(found.len(), roots.len());
+        for root in roots {
+            assert!(
+                found.iter().any(|&z| (z - root).norm() < 1e-9),
+                "{} is missing from {:?}",
+                root,
+                found
+            );
+        }
+
+        // Guesses near the roots keep them in order.
+        let guesses: Vec<Complex64> = roots.iter().map(|&root| root + c(0.01, -0.01)).collect();
+        assert_close(&find_roots(&coefficients, &guesses), &roots);
+    }
+
+    #[test]
+    fn group_roots_recovers_multiplicity() {
+        // (z - 1)^3 (z + 2)
+        let coefficients = expand_roots([c(1.0, 0.0), c(1.0, 0.0), c(1.0, 0.0), c(-2.0, 0.0)]);
+        let roots = find_roots(&coefficients, &[]);
+        let mut groups = group_roots(&coefficients, &roots);
+        groups.sort_by_key(|&(_, multiplicity)| multiplicity);
+        assert_eq!(groups.len(), 2, "{:?}", groups);
+        let (simple, simple_multiplicity) = groups[0];
+        let (triple, triple_multiplicity) = groups[1];
+        assert_eq!((simple_multiplicity, triple_multiplicity), (1, 3));
+        assert!((simple - c(-2.0, 0.0)).norm() < 1e-9, "{}", simple);
+        assert!((triple - c(1.0, 0.0)).norm() < 1e-9, "{}", triple);
+    }
+
+    #[test]
+    fn group_roots_keeps_close_simple_roots_apart() {
+        let roots = [c(1.0, 0.0), c(1.01, 0.0)];
+        let coefficients = expand_roots(roots);
+        let groups = group_roots(&coefficients, &find_roots(&coefficients, &roots));
+        assert_eq!(groups.len(), 2, "{:?}", groups);
+        assert!(groups.iter().all(|&(_, multiplicity)| multiplicity == 1));
+    }
+}
@@ -0,0 +1,127 @@
+//! Points on the complex plane with arbitrary precision.
+
+use std::str::FromStr;
+
+use anyhow::Context;
+use dashu_float::DBig;
+use glam::DVec2;
+use serde::{de, ser::SerializeTuple, Deserialize, Deserializer, Serialize, Serializer};
+
+/// A point on the complex plane, with as many decimal digits as needed.
+///
+/// Used for the camera position, which needs to be more precise than `f64`
+/// for deep zooms. It is serialized as a pair of decimal strings, so presets
+/// keep every digit.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PrecisePoint {
+    pub re: DBig,
+    pub im: DBig,
+}
+
+impl PrecisePoint {
+    /// Parses the real and imaginary parts from decimal strings, e.g.
+    /// `"-0.5"` or `"1.25e-40"`.
+    pub fn parse(re: &str, im: &str) -> anyhow::Result<Self> {
+        Ok(Self {
+            re: parse_decimal(re)?,
+            im: parse_decimal(im)?,
+        })
+    }
+
+    /// The nearest `f64` point.
+    pub fn to_dvec2(&self) -> DVec2 {
+        DVec2::new(self.re.to_f64().value(), self.im.to_f64().value())
+    }
+
+    /// Returns this point moved by `offset`, without any rounding.
+    ///
+    /// Non-finite offsets are ignored.
+    pub fn translated(&self, offset: DVec2) -> Self {
+        if !offset.is_finite() {
+            return self.clone();
+        }
+        Self {
+            re: exact_add(&self.re, &from_f64(offset.x)),
+            im: exact_add(&self.im, &from_f64(offset.y)),
+        }
+    }
+
+    /// The offset from this point to `other`, which is exact before it is
+    /// rounded to `f64`.
+    pub fn offset_to(&self, other: &Self) -> DVec2 {
+        let re = exact_add(&other.re, &-self.re.clone());
+        let im = exact_add(&other.im, &-self.im.clone());
+        DVec2::new(re.to_f64().value(), im.to_f64().value())
+    }
+}
+
+impl From<DVec2> for PrecisePoint {
+    fn from(v: DVec2) -> Self {
+        Self::default().translated(v)
+    }
+}
+
+fn parse_decimal(s: &str) -> anyhow::Result<DBig> {
+    DBig::from_str(s.trim()).with_context(|| format!("invalid decimal number {:?}", s))
+}
+
+/// Converts a finite `f64` to the shortest decimal that rounds back to it.
+fn from_f64(x: f64) -> DBig {
+    DBig::from_str(&format!("{:e}", x)).expect("finite floats are valid decimals")
+}
+
+/// Adds two decimals, with enough precision to keep every digit of both.
+fn exact_add(a: &DBig, b: &DBig) -> DBig {
+    if b.repr().is_zero() {
+        return a.clone();
+    }
+    if a.repr().is_zero() {
+        return b.clone();
+    }
+    // The digits of `significand * 10^exponent` span the powers of ten from
+    // `exponent` to `exponent + digits - 1`.
+    let lowest = a.repr().exponent().min(b.repr().exponent());
+    let highest = (a.repr().exponent() + a.repr().digits() as isize)
+        .max(b.repr().exponent() + b.repr().digits() as isize);
+    // One more digit for the carry.
+    let precision = (highest - lowest) as usize + 1;
+    a.clone().with_precision(precision).value() + b.clone().with_precision(precision).value()
+}
+
+impl Serialize for PrecisePoint {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut tuple = serializer.serialize_tuple(2)?;
+        tuple.serialize_element(&self.re.to_string())?;
+        tuple.serialize_element(&self.im.to_string())?;
+        tuple.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for PrecisePoint {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let [re, im] = <[Coordinate; 2]>::deserialize(deserializer)?;
+        Ok(Self {
+            re: re.into_decimal().map_err(de::Error::custom)?,
+            im: im.into_decimal().map_err(de::Error::custom)?,
+        })
+    }
+}
+
+/// A coordinate in a preset, which is either a decimal string or a plain
+/// number, as written by older versions.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Coordinate {
+    Number(f64),
+    Decimal(String),
+}
+
+impl Coordinate {
+    fn into_decimal(self) -> anyhow::Result<DBig> {
+        match self {
+            Self::Number(x) if x.is_finite() => Ok(from_f64(x)),
+            Self::Number(x) => anyhow::bail!("invalid coordinate {}", x),
+            Self::Decimal(s) => parse_decimal(&s),
+        }
+    }
+}
@@ -1,43 +1,84 @@
 use std::time::Duration;
 
-use glam::{Vec3, Vec4Swizzles};
+use glam::{DVec2, Vec2, Vec3, Vec4Swizzles};
 use imgui::Condition;
 use imgui_winit_support::HiDpiMode;
+use num_complex::Complex64;
 use wgpu::CommandEncoder;
 use winit::event_loop::{EventLoop, EventLoopProxy};
 
 use crate::{
-    config::{Config, ConfigChangeEvent},
-    AppEvent, Event, GraphicsContext,
+    animation::{Easing, RootPath, Timeline, TimelineEvent},
+    attractor::{self, Attractor},
+    config::{
+        Basins, Coloring, Config, ConfigChangeEvent, Method, Nova, MAX_HOUSEHOLDER_ORDER,
+        MAX_MULTIPLICITY,
+    },
+    deep,
+    expr::Function,
+    fractal::Precision,
+    history::History,
+    overlay::RootHandles,
+    poly,
+    precise::PrecisePoint,
+    AppEvent, Event, GraphicsContext, Surface,
 };
 
+/// What the UI shows of the app, besides the config.
+pub struct AppState<'a> {
+    pub root_handles: &'a RootHandles,
+    /// The precision that the fractal was drawn with.
+    pub precision: Precision,
+    pub attractors: &'a [Attractor],
+    pub history: &'a History,
+    pub timeline: &'a Timeline,
+    /// The current time of the animation, and whether it is playing.
+    pub animation_time: f64,
+    pub playing: bool,
+}
+
 pub struct UiRenderer {
     gfx: GraphicsContext,
+    surface: Surface,
     imgui: imgui::Context,
     platform: imgui_winit_support::WinitPlatform,
     renderer: imgui_wgpu::Renderer,
     event_proxy: EventLoopProxy<AppEvent>,
+    preset_path: String,
+    export_path: String,
+    export_size: [i32; 2],
+    animation_path: String,
+    /// The time at which "Add keyframe" inserts the next keyframe.
+    keyframe_time: f32,
 }
 
 impl UiRenderer {
-    pub fn new(gfx: &GraphicsContext, event_loop: &EventLoop<AppEvent>) -> Self {
+    pub fn new(gfx: &GraphicsContext, surface: &Surface, event_loop: &EventLoop<AppEvent>) -> Self {
         let mut imgui = imgui::Context::create();
         imgui.style_mut().use_classic_colors();
 
         let mut platform = imgui_winit_support::WinitPlatform::init(&mut imgui);
-        platform.attach_window(imgui.io_mut(), &gfx.window, HiDpiMode::Default);
+        platform.attach_window(imgui.io_mut(), &surface.window, HiDpiMode::Default);
 
-        let mut renderer_config = imgui_wgpu::RendererConfig::default();
-        renderer_config.texture_format = gfx.render_format;
+        let renderer_config = imgui_wgpu::RendererConfig {
+            texture_format: gfx.render_format,
+            ..Default::default()
+        };
         let renderer =
             imgui_wgpu::Renderer::new(&mut imgui, &gfx.device, &gfx.queue, renderer_config);
 
         Self {
             gfx: gfx.clone(),
+            surface: surface.clone(),
             imgui,
             platform,
             renderer,
             event_proxy: event_loop.create_proxy(),
+            preset_path: "preset.toml".into(),
+            export_path: "export.png".into(),
+            export_size: [7680, 4320],
+            animation_path: "animation.toml".into(),
+            keyframe_time: 0.0,
         }
     }
 
@@ -47,7 +88,7 @@ impl UiRenderer {
 
     pub fn handle_event(&mut self, event: &Event) {
         self.platform
-            .handle_event(self.imgui.io_mut(), &self.gfx.window, &event)
+            .handle_event(self.imgui.io_mut(), &self.surface.window, event)
     }
 
     pub fn has_keyboard_focus(&self) -> bool {
@@ -63,13 +104,29 @@ impl UiRenderer {
         command_encoder: &mut CommandEncoder,
         frame: &wgpu::TextureView,
         config: &Config,
+        state: AppState,
     ) -> anyhow::Result<()> {
+        let AppState {
+            root_handles,
+            precision,
+            attractors,
+            history,
+            timeline,
+            animation_time,
+            playing,
+        } = state;
         self.platform
-            .prepare_frame(self.imgui.io_mut(), &self.gfx.window)?;
+            .prepare_frame(self.imgui.io_mut(), &self.surface.window)?;
 
         let ui = self.imgui.frame();
+        root_handles.draw(&ui, config);
         // Manually split borrow outside of closure:
         let event_proxy = &self.event_proxy;
+        let preset_path = &mut self.preset_path;
+        let export_path = &mut self.export_path;
+        let export_size = &mut self.export_size;
+        let animation_path = &mut self.animation_path;
+        let keyframe_time = &mut self.keyframe_time;
         let config_change = |event| {
             event_proxy.send_event(AppEvent::ConfigChange(event)).ok();
         };
@@ -86,20 +143,220 @@ impl UiRenderer {
                     {
                         config_change(ConfigChangeEvent::NumIterations(num_iterations.max(0) as _));
                     }
+                    let mut method = Method::ALL
+                        .iter()
+                        .position(|&m| m == config.method)
+                        .unwrap_or(0);
+                    if ui.combo("Method", &mut method, &Method::ALL, |m| m.name().into()) {
+                        config_change(ConfigChangeEvent::Method(Method::ALL[method]));
+                    }
+                    if config.method == Method::Householder {
+                        let mut order = config.householder_order as i32;
+                        if ui.input_int("Order", &mut order).step(1).build() {
+                            config_change(ConfigChangeEvent::HouseholderOrder(
+                                order.clamp(1, MAX_HOUSEHOLDER_ORDER as i32) as _,
+                            ));
+                        }
+                    }
+                    let mut relaxation = config.relaxation.to_array();
+                    if imgui::Drag::new("Relaxation")
+                        .speed(0.005)
+                        .build_array(&ui, &mut relaxation)
+                    {
+                        config_change(ConfigChangeEvent::Relaxation(relaxation.into()));
+                    }
+                    let mut nova = Nova::ALL
+                        .iter()
+                        .position(|&n| n == config.nova)
+                        .unwrap_or(0);
+                    if ui.combo("Nova", &mut nova, &Nova::ALL, |n| n.name().into()) {
+                        config_change(ConfigChangeEvent::Nova(Nova::ALL[nova]));
+                    }
+                    if config.nova == Nova::Julia {
+                        let mut nova_c = config.nova_c.to_array();
+                        if imgui::Drag::new("c")
+                            .speed(0.005)
+                            .build_array(&ui, &mut nova_c)
+                        {
+                            config_change(ConfigChangeEvent::NovaC(nova_c.into()));
+                        }
+                    }
+                    let mut epsilon = config.epsilon;
+                    if imgui::Slider::new("Epsilon", 1e-7, 1e-1)
+                        .display_format("%.1e")
+                        .flags(imgui::SliderFlags::LOGARITHMIC)
+                        .build(&ui, &mut epsilon)
+                    {
+                        config_change(ConfigChangeEvent::Epsilon(epsilon));
+                    }
+                    let mut coloring = Coloring::ALL
+                        .iter()
+                        .position(|&c| c == config.coloring)
+                        .unwrap_or(0);
+                    if ui.combo("Coloring", &mut coloring, &Coloring::ALL, |c| {
+                        c.name().into()
+                    }) {
+                        config_change(ConfigChangeEvent::Coloring(Coloring::ALL[coloring]));
+                    }
+                    let mut basins = Basins::ALL
+                        .iter()
+                        .position(|&b| b == config.basins)
+                        .unwrap_or(0);
+                    if ui.combo("Basins", &mut basins, &Basins::ALL, |b| b.name().into()) {
+                        config_change(ConfigChangeEvent::Basins(Basins::ALL[basins]));
+                    }
+                    let mut color = config.non_convergence_color.xyz().to_array();
+                    if imgui::ColorEdit::new("Non-convergence color", &mut color)
+                        .alpha(false)
+                        .build(&ui)
+                    {
+                        config_change(ConfigChangeEvent::NonConvergenceColor(
+                            Vec3::from(color).extend(1.0),
+                        ));
+                    }
+                    let mut max_cycle_period = config.max_cycle_period as i32;
+                    if ui
+                        .input_int("Max cycle period", &mut max_cycle_period)
+                        .step(1)
+                        .build()
+                    {
+                        config_change(ConfigChangeEvent::MaxCyclePeriod(
+                            max_cycle_period.max(0) as _
+                        ));
+                    }
+                    if config.max_cycle_period > 0 {
+                        let mut color = config.cycle_color.xyz().to_array();
+                        if imgui::ColorEdit::new("Cycle color", &mut color)
+                            .alpha(false)
+                            .build(&ui)
+                        {
+                            config_change(ConfigChangeEvent::CycleColor(
+                                Vec3::from(color).extend(1.0),
+                            ));
+                        }
+                    }
+                    if config.basins == Basins::Attractors {
+                        draw_attractors(&ui, attractors);
+                    } else if config.max_cycle_period > 0 {
+                        draw_cycles(&ui, config, attractors);
+                    }
                 }
                 if ui.collapsing_header("Camera", imgui::TreeNodeFlags::DEFAULT_OPEN) {
-                    let mut position = config.camera.position.to_array();
-                    if ui.input_float2("Position", &mut position).build() {
-                        config_change(ConfigChangeEvent::CameraPosition(position.into()));
-                    };
+                    // The position is edited as text, so that no digits are
+                    // lost. The change is applied when pressing enter.
+                    let mut re = config.camera.position.re.to_string();
+                    let mut im = config.camera.position.im.to_string();
+                    let re_changed = ui
+                        .input_text("Real", &mut re)
+                        .enter_returns_true(true)
+                        .build();
+                    let im_changed = ui
+                        .input_text("Imaginary", &mut im)
+                        .enter_returns_true(true)
+                        .build();
+                    if re_changed || im_changed {
+                        match PrecisePoint::parse(&re, &im) {
+                            Ok(position) => {
+                                config_change(ConfigChangeEvent::CameraPosition(position))
+                            }
+                            Err(err) => log::error!("{:#}", err),
+                        }
+                    }
+                    // There is no double precision input field, so this is a
+                    // drag widget. Ctrl+click to type in a value.
                     let mut zoom = config.camera.zoom;
-                    if ui
-                        .input_float("Zoom", &mut zoom)
-                        .step(config.camera.zoom * 0.01)
-                        .build()
+                    if imgui::Drag::new("Zoom")
+                        .speed((config.camera.zoom * 0.01) as f32)
+                        .display_format("%.6g")
+                        .flags(imgui::SliderFlags::NO_ROUND_TO_FORMAT)
+                        .build(&ui, &mut zoom)
                     {
                         config_change(ConfigChangeEvent::CameraZoom(zoom));
                     };
+                    ui.text(format!("Precision: {}", precision.name()));
+                    let display_size = Vec2::from(ui.io().display_size);
+                    if deep::is_needed(&config.camera.region(display_size), display_size) {
+                        if config.function.is_some() {
+                            ui.text_wrapped(
+                                "This is too deep for the GPU. Only polynomials can be \
+                                 exported this deep.",
+                            );
+                        } else {
+                            ui.text_wrapped(
+                                "This is too deep for the GPU. Exports are rendered on the \
+                                 CPU with arbitrary precision.",
+                            );
+                        }
+                    }
+                }
+
+                if ui.collapsing_header("Function", imgui::TreeNodeFlags::DEFAULT_OPEN) {
+                    // Like the camera position, the expressions are applied
+                    // when pressing enter. An empty function selects the
+                    // polynomial.
+                    let mut function = config
+                        .function
+                        .as_ref()
+                        .map_or_else(String::new, |function| function.source().to_owned());
+                    if ui
+                        .input_text("f(z)", &mut function)
+                        .enter_returns_true(true)
+                        .build()
+                    {
+                        if function.trim().is_empty() {
+                            config_change(ConfigChangeEvent::Function(None));
+                        } else {
+                            match Function::parse(&function) {
+                                Ok(function) => {
+                                    config_change(ConfigChangeEvent::Function(Some(function)))
+                                }
+                                Err(err) => log::error!("{:#}", err),
+                            }
+                        }
+                    }
+                    if config.function.is_some() {
+                        ui.text_wrapped(
+                            "The roots are found numerically around the camera, and are \
+                             only used for coloring.",
+                        );
+                        if ui.button("Find roots") {
+                            config_change(ConfigChangeEvent::FindFunctionRoots);
+                        }
+                    } else {
+                        let coefficients: Vec<Complex64> = config
+                            .coefficients
+                            .iter()
+                            .map(|c| Complex64::new(c.x, c.y))
+                            .collect();
+                        let mut expression = poly::format(&coefficients);
+                        if ui
+                            .input_text("p(z)", &mut expression)
+                            .enter_returns_true(true)
+                            .build()
+                        {
+                            match poly::parse(&expression) {
+                                Ok(coefficients) => config_change(ConfigChangeEvent::Coefficients(
+                                    coefficients
+                                        .into_iter()
+                                        .map(|c| DVec2::new(c.re, c.im))
+                                        .collect(),
+                                )),
+                                Err(err) => log::error!("{:#}", err),
+                            }
+                        }
+                        for (i, coefficient) in config.coefficients.iter().enumerate().rev() {
+                            let mut value = coefficient.to_array();
+                            if imgui::Drag::new(format!("z^{}", i))
+                                .speed(0.005)
+                                .display_format("%.6g")
+                                .build_array(&ui, &mut value)
+                            {
+                                let mut coefficients = config.coefficients.clone();
+                                coefficients[i] = value.into();
+                                config_change(ConfigChangeEvent::Coefficients(coefficients));
+                            }
+                        }
+                    }
                 }
 
                 if ui.collapsing_header("Roots", imgui::TreeNodeFlags::DEFAULT_OPEN) {
@@ -112,6 +369,18 @@ impl UiRenderer {
                                     position: position.into(),
                                 });
                             }
+                            let mut multiplicity = root.multiplicity as i32;
+                            if ui
+                                .input_int("Multiplicity", &mut multiplicity)
+                                .step(1)
+                                .build()
+                            {
+                                config_change(ConfigChangeEvent::RootMultiplicity {
+                                    index: i,
+                                    multiplicity: multiplicity.clamp(1, MAX_MULTIPLICITY as i32)
+                                        as _,
+                                });
+                            }
                             let mut color = root.color.xyz().to_array();
                             if imgui::ColorEdit::new("Color", &mut color)
                                 .alpha(false)
@@ -122,6 +391,31 @@ impl UiRenderer {
                                     color: Vec3::from(color).extend(1.0),
                                 });
                             }
+                            ui.disabled(i == 0, || {
+                                if ui.button("Up") {
+                                    config_change(ConfigChangeEvent::MoveRoot {
+                                        from: i,
+                                        to: i - 1,
+                                    });
+                                }
+                            });
+                            ui.same_line();
+                            ui.disabled(i + 1 == config.roots.len(), || {
+                                if ui.button("Down") {
+                                    config_change(ConfigChangeEvent::MoveRoot {
+                                        from: i,
+                                        to: i + 1,
+                                    });
+                                }
+                            });
+                            ui.same_line();
+                            if ui.button("Duplicate") {
+                                config_change(ConfigChangeEvent::DuplicateRoot { index: i });
+                            }
+                            ui.same_line();
+                            if ui.button("Remove") {
+                                config_change(ConfigChangeEvent::RemoveRoot { index: i });
+                            }
                         });
                     }
 
@@ -129,9 +423,190 @@ impl UiRenderer {
                         config_change(ConfigChangeEvent::AddRoot);
                     }
                 }
+
+                if ui.collapsing_header("Preset", imgui::TreeNodeFlags::empty()) {
+                    ui.input_text("Path", preset_path).build();
+                    if ui.button("Save preset") {
+                        event_proxy
+                            .send_event(AppEvent::SavePreset(preset_path.as_str().into()))
+                            .ok();
+                    }
+                    ui.same_line();
+                    if ui.button("Load preset") {
+                        event_proxy
+                            .send_event(AppEvent::LoadPreset(preset_path.as_str().into()))
+                            .ok();
+                    }
+                }
+
+                if ui.collapsing_header("Export", imgui::TreeNodeFlags::empty()) {
+                    ui.input_text("Path##export", export_path).build();
+                    ui.input_int2("Size", export_size).build();
+                    if ui.button("Export PNG") {
+                        event_proxy
+                            .send_event(AppEvent::ExportPng {
+                                path: export_path.as_str().into(),
+                                width: export_size[0].max(1) as _,
+                                height: export_size[1].max(1) as _,
+                            })
+                            .ok();
+                    }
+                }
+            });
+
+        imgui::Window::new("History")
+            .size([220.0, 300.0], Condition::FirstUseEver)
+            .position([340.0, 20.0], Condition::FirstUseEver)
+            .build(&ui, || {
+                ui.disabled(!history.can_undo(), || {
+                    if ui.button("Undo") {
+                        event_proxy.send_event(AppEvent::Undo).ok();
+                    }
+                });
+                ui.same_line();
+                ui.disabled(!history.can_redo(), || {
+                    if ui.button("Redo") {
+                        event_proxy.send_event(AppEvent::Redo).ok();
+                    }
+                });
+                ui.separator();
+                // Clicking an entry goes to the config right after it, and
+                // the undone entries are grayed out.
+                let labels = std::iter::once("Start").chain(history.labels());
+                for (position, label) in labels.enumerate() {
+                    let undone = position > history.position();
+                    let style = undone.then(|| {
+                        ui.push_style_color(
+                            imgui::StyleColor::Text,
+                            ui.style_color(imgui::StyleColor::TextDisabled),
+                        )
+                    });
+                    if imgui::Selectable::new(format!("{}##{}", label, position))
+                        .selected(position == history.position())
+                        .build(&ui)
+                    {
+                        event_proxy.send_event(AppEvent::GoToHistory(position)).ok();
+                    }
+                    if let Some(style) = style {
+                        style.pop();
+                    }
+                }
+            });
+
+        imgui::Window::new("Animation")
+            .size([320.0, 300.0], Condition::FirstUseEver)
+            .position([340.0, 340.0], Condition::FirstUseEver)
+            .collapsed(true, Condition::FirstUseEver)
+            .build(&ui, || {
+                let timeline_change = |event| {
+                    event_proxy.send_event(AppEvent::Timeline(event)).ok();
+                };
+                let seek = |time| {
+                    event_proxy.send_event(AppEvent::SeekAnimation(time)).ok();
+                };
+                let duration = timeline.duration();
+                ui.disabled(timeline.keyframes.len() < 2, || {
+                    if ui.button(if playing { "Pause" } else { "Play" }) {
+                        event_proxy
+                            .send_event(AppEvent::PlayAnimation(!playing))
+                            .ok();
+                    }
+                    ui.same_line();
+                    if ui.button("Rewind") {
+                        seek(0.0);
+                    }
+                    let mut time = animation_time as f32;
+                    if imgui::Slider::new("Time", 0.0, duration as f32)
+                        .display_format("%.2f s")
+                        .build(&ui, &mut time)
+                    {
+                        seek(time as f64);
+                    }
+                });
+
+                imgui::Drag::new("##keyframe_time")
+                    .speed(0.05)
+                    .range(0.0, f32::MAX)
+                    .display_format("%.2f s")
+                    .build(&ui, keyframe_time);
+                ui.same_line();
+                if ui.button("Add keyframe") {
+                    timeline_change(TimelineEvent::AddKeyframe {
+                        time: keyframe_time.max(0.0) as f64,
+                    });
+                    *keyframe_time = keyframe_time.max(0.0) + 2.0;
+                }
+                for (i, keyframe) in timeline.keyframes.iter().enumerate() {
+                    // The index keeps the tree node open while the time is
+                    // dragged.
+                    let label = format!("{:.2} s###keyframe{}", keyframe.time, i);
+                    imgui::TreeNode::new(&label).build(&ui, || {
+                        let mut time = keyframe.time as f32;
+                        if imgui::Drag::new("Time")
+                            .speed(0.05)
+                            .display_format("%.2f s")
+                            .build(&ui, &mut time)
+                        {
+                            timeline_change(TimelineEvent::KeyframeTime {
+                                index: i,
+                                time: time as f64,
+                            });
+                        }
+                        if ui.is_item_deactivated_after_edit() {
+                            timeline_change(TimelineEvent::SortKeyframes);
+                        }
+                        let mut easing = Easing::ALL
+                            .iter()
+                            .position(|&e| e == keyframe.easing)
+                            .unwrap_or(0);
+                        if ui.combo("Easing", &mut easing, &Easing::ALL, |e| e.name().into()) {
+                            timeline_change(TimelineEvent::KeyframeEasing {
+                                index: i,
+                                easing: Easing::ALL[easing],
+                            });
+                        }
+                        let mut root_path = RootPath::ALL
+                            .iter()
+                            .position(|&p| p == keyframe.root_path)
+                            .unwrap_or(0);
+                        if ui.combo("Root path", &mut root_path, &RootPath::ALL, |p| {
+                            p.name().into()
+                        }) {
+                            timeline_change(TimelineEvent::KeyframeRootPath {
+                                index: i,
+                                root_path: RootPath::ALL[root_path],
+                            });
+                        }
+                        if ui.button("Go to") {
+                            seek(keyframe.time);
+                        }
+                        ui.same_line();
+                        if ui.button("Update") {
+                            timeline_change(TimelineEvent::UpdateKeyframe { index: i });
+                        }
+                        ui.same_line();
+                        if ui.button("Remove") {
+                            timeline_change(TimelineEvent::RemoveKeyframe { index: i });
+                        }
+                    });
+                }
+
+                ui.separator();
+                ui.input_text("Path##animation", animation_path).build();
+                if ui.button("Save animation") {
+                    event_proxy
+                        .send_event(AppEvent::SaveAnimation(animation_path.as_str().into()))
+                        .ok();
+                }
+                ui.same_line();
+                if ui.button("Load animation") {
+                    event_proxy
+                        .send_event(AppEvent::LoadAnimation(animation_path.as_str().into()))
+                        .ok();
+                }
             });
 
-        self.platform.prepare_render(&ui, &self.gfx.window);
+        self.platform.prepare_render(&ui, &self.surface.window);
 
         let mut render_pass = command_encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
             label: Some("UiRenderer.render_pass"),
@@ -157,3 +632,54 @@ impl UiRenderer {
         Ok(())
     }
 }
+
+/// Lists the attractors that the basins are colored by, pointing out the ones
+/// that aren't roots.
+fn draw_attractors(ui: &imgui::Ui, attractors: &[Attractor]) {
+    ui.text(format!("{} attractors", attractors.len()));
+    for (i, attractor) in attractors.iter().enumerate() {
+        imgui::ColorButton::new(format!("##attractor{}", i), attractor.color.into()).build(ui);
+        ui.same_line();
+        ui.text(describe_attractor(attractor));
+    }
+}
+
+/// Lists the attracting cycles around the camera, which all share the cycle
+/// color unless the basins are colored by attractor.
+fn draw_cycles(ui: &imgui::Ui, config: &Config, attractors: &[Attractor]) {
+    if config.nova == Nova::Mandelbrot {
+        // Every pixel iterates a different map, see `attractor::find`.
+        ui.text_wrapped("Cycles are colored, but not listed for the Mandelbrot variant.");
+        return;
+    }
+    let cycles: Vec<&Attractor> = attractors
+        .iter()
+        .filter(|attractor| attractor.period() > 1)
+        .collect();
+    if cycles.is_empty() {
+        ui.text("No attracting cycles around the camera");
+    }
+    for cycle in cycles {
+        ui.text(describe_attractor(cycle));
+    }
+}
+
+/// What kind of attractor this is, and which share of the sampled orbits
+/// ended up there.
+fn describe_attractor(attractor: &Attractor) -> String {
+    let point = attractor.points[0];
+    let description = match attractor.root {
+        Some(index) => format!("Root {}", index + 1),
+        None if attractor.period() == 1 => {
+            format!("Fixed point at {:.4} {:+.4}i", point.re, point.im)
+        }
+        None => format!(
+            "{}-cycle through {:.4} {:+.4}i",
+            attractor.period(),
+            point.re,
+            point.im
+        ),
+    };
+    let share = 100.0 * attractor.count as f64 / attractor::GRID_SIZE.pow(2) as f64;
+    format!("{} ({:.1}%)", description, share)
+}